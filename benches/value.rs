@@ -0,0 +1,167 @@
+// compares the NaN-boxed `Value` against the plain enum it replaced; boxed values copy and
+// branch faster, but arithmetic is slower for the unpacking
+//
+//     cargo bench --bench value
+
+extern crate niels;
+
+use std::hint::black_box;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use niels::interpreter::{Value, Variant};
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Enum {
+    Float(f64),
+    Bool(bool),
+    Int(i32),
+    Char(char),
+    Pointer(u32),
+    Nil,
+}
+
+impl Enum {
+    fn truthy(&self) -> bool {
+        !matches!(self, Enum::Bool(false) | Enum::Nil)
+    }
+}
+
+const VALUES: usize = 10_000;
+const ROUNDS: usize = 200;
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    // warm up once, then keep the best round
+    f();
+
+    (0 .. 5)
+        .map(|_| {
+            let start = Instant::now();
+
+            f();
+
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, boxed: Duration, plain: Duration) {
+    println!(
+        "{:<12} nanbox {:>10.2?}   enum {:>10.2?}   ({:.2}x)",
+        name,
+        boxed,
+        plain,
+        plain.as_secs_f64() / boxed.as_secs_f64()
+    )
+}
+
+fn main() {
+    println!(
+        "size         nanbox {:>8} B   enum {:>8} B\n",
+        mem::size_of::<Value>(),
+        mem::size_of::<Enum>()
+    );
+
+    let boxed = (0 .. VALUES)
+        .map(|i| match i % 4 {
+            0 => Value::int(i as i32),
+            1 => Value::float(i as f64),
+            2 => Value::bool(i % 3 == 0),
+            _ => Value::pointer(i as u32),
+        })
+        .collect::<Vec<Value>>();
+
+    let plain = (0 .. VALUES)
+        .map(|i| match i % 4 {
+            0 => Enum::Int(i as i32),
+            1 => Enum::Float(i as f64),
+            2 => Enum::Bool(i % 3 == 0),
+            _ => Enum::Pointer(i as u32),
+        })
+        .collect::<Vec<Enum>>();
+
+    // moving values between stacks, like `LoadLocal` and `SetLocal` do
+    report(
+        "copy",
+        time(|| {
+            let mut stack = Vec::with_capacity(VALUES);
+
+            for _ in 0 .. ROUNDS {
+                stack.clear();
+                stack.extend(black_box(&boxed).iter().copied());
+            }
+
+            black_box(&stack);
+        }),
+        time(|| {
+            let mut stack = Vec::with_capacity(VALUES);
+
+            for _ in 0 .. ROUNDS {
+                stack.clear();
+                stack.extend(black_box(&plain).iter().copied());
+            }
+
+            black_box(&stack);
+        }),
+    );
+
+    // unpacking, adding and repacking, like the arithmetic opcodes do
+    report(
+        "arithmetic",
+        time(|| {
+            let mut sum = Value::int(0);
+
+            for _ in 0 .. ROUNDS {
+                for value in black_box(&boxed) {
+                    sum = match (sum.unpack(), value.unpack()) {
+                        (Variant::Int(a), Variant::Int(b)) => Value::int(a.wrapping_add(b)),
+                        (Variant::Int(a), Variant::Float(b)) => Value::int(a.wrapping_add(b as i32)),
+                        _ => sum,
+                    }
+                }
+            }
+
+            black_box(sum);
+        }),
+        time(|| {
+            let mut sum = Enum::Int(0);
+
+            for _ in 0 .. ROUNDS {
+                for value in black_box(&plain) {
+                    sum = match (sum, *value) {
+                        (Enum::Int(a), Enum::Int(b)) => Enum::Int(a.wrapping_add(b)),
+                        (Enum::Int(a), Enum::Float(b)) => Enum::Int(a.wrapping_add(b as i32)),
+                        _ => sum,
+                    }
+                }
+            }
+
+            black_box(sum);
+        }),
+    );
+
+    // branching on a value, like `JmpIfNot` does
+    report(
+        "truthy",
+        time(|| {
+            let mut count = 0;
+
+            for _ in 0 .. ROUNDS {
+                count += black_box(&boxed).iter().filter(|value| value.truthy()).count()
+            }
+
+            black_box(count);
+        }),
+        time(|| {
+            let mut count = 0;
+
+            for _ in 0 .. ROUNDS {
+                count += black_box(&plain).iter().filter(|value| value.truthy()).count()
+            }
+
+            black_box(count);
+        }),
+    );
+}
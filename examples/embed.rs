@@ -0,0 +1,41 @@
+extern crate niels;
+
+use niels::error::Emitter;
+use niels::interpreter::*;
+use niels::Engine;
+
+use std::io;
+
+fn shout(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    let shouted = vm.display(&args[0]).to_uppercase();
+
+    Ok(shouted.into_value(vm))
+}
+
+fn main() {
+    let mut engine = Engine::new();
+
+    engine.register_native("shout", 1, shout);
+
+    let result = engine
+        .eval::<()>("pub funk greet(name): return shout(name)\nlimit = 3")
+        .and_then(|_| engine.call::<_, String>("greet", ("niels",)))
+        .and_then(|greeting| {
+            let limit: Option<i32> = engine.global("limit");
+
+            println!("{} (limit {:?})", greeting, limit);
+
+            engine.eval::<Vec<i32>>("[1, 2, limit]")
+        });
+
+    match result {
+        Ok(numbers) => println!("{:?}", numbers),
+        Err(diagnostics) => {
+            let mut emitter = Emitter::new(io::stdout());
+
+            for diagnostic in &diagnostics {
+                emitter.emit(diagnostic)
+            }
+        },
+    }
+}
@@ -0,0 +1,8 @@
+extern crate colored;
+extern crate nanbox;
+
+mod niels;
+
+pub use niels::engine::*;
+pub use niels::parser::parse;
+pub use niels::{error, interpreter, lexer, parser, source};
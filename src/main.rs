@@ -1,102 +1,251 @@
-extern crate colored;
-extern crate nanbox;
+extern crate niels;
 
-mod niels;
-
-use niels::lexer::*;
-use niels::parser::*;
-use niels::source::*;
+use niels::error::Response::Wrong;
+use niels::error::*;
 use niels::interpreter::*;
+use niels::parse;
+use niels::source::*;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
 
-fn test_parser() {
-    let content = r#"
-pub funk foo:
-  hello = 10
+const USAGE: &str = "usage: niels run [--gc-stats] <file> | niels compile <file> [-o <output>] \
+                     | niels dis <file> | niels repl";
 
-  a = hello + fff
+// host funks every script can call
+const NATIVES: &[(&str, u32, NativeFn)] = &[("print", 1, print), ("error", 1, error)];
 
-  b = {
-    y: 100
-    bax: 200
-  }
+fn print(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    println!("{}", vm.display(&args[0]));
 
-  x = 10_000
-  x += b.bax
+    Ok(Value::nil())
+}
+
+// stops the script with a message of its own
+fn error(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, RuntimeError> {
+    Err(RuntimeError::new(RuntimeErrorKind::Native(vm.display(&args[0]))))
+}
 
-  return r"hey\n\n\n"
+fn machine() -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
 
-funk bar(a, b): return [a, b]
+    for &(name, arity, function) in NATIVES {
+        vm.register_native(name, arity, function)
+    }
 
-foo()
-bar(1, 2 + 10)
-"#;
+    vm
+}
 
-    let source = Source::from(
-        "<main>",
-        content.lines().map(|x| x.into()).collect::<Vec<String>>(),
-    );
-    let lexer = Lexer::default(content.chars().collect(), &source);
+// a compiler that knows about every native `vm` has, and looks for modules in `NIELS_PATH`
+fn compiler(vm: &VirtualMachine) -> Compiler {
+    let mut compiler = Compiler::new();
 
-    let mut tokens = Vec::new();
+    for (name, arity) in vm.natives() {
+        compiler.declare_native(name, arity)
+    }
 
-    for token_result in lexer {
-        if let Ok(token) = token_result {
-            tokens.push(token)
-        } else {
-            return;
+    if let Some(paths) = env::var_os("NIELS_PATH") {
+        for path in env::split_paths(&paths) {
+            compiler.add_search_path(path)
         }
     }
 
-    let mut parser = Parser::new(tokens, &source);
+    compiler
+}
+
+fn read_source(path: &str) -> Result<Source, Vec<Diagnostic>> {
+    Source::new(path.to_string()).map_err(|error| {
+        vec!(Diagnostic::new(
+            Wrong(format!("couldn't read file: {}", error)),
+            &FilePath(path.to_string()),
+            None,
+        ))
+    })
+}
+
+fn compile_source(source: &Source, vm: &VirtualMachine) -> Result<Program, Vec<Diagnostic>> {
+    let ast = parse(source)?;
+
+    let mut compiler = compiler(vm);
+
+    compiler.compile(&ast, source).cloned().map_err(|diagnostic| vec!(diagnostic))
+}
+
+// `.nlsc` files are loaded as they are, anything else is compiled first
+fn load_program(path: &str, vm: &VirtualMachine) -> Result<(Program, FilePath), Vec<Diagnostic>> {
+    if !path.ends_with(".nlsc") {
+        let source = read_source(path)?;
+
+        return Ok((compile_source(&source, vm)?, source.file));
+    }
+
+    let wrong = |message: String| vec!(Diagnostic::new(Wrong(message), &FilePath(path.to_string()), None));
+
+    let bytes = fs::read(path).map_err(|error| wrong(format!("couldn't read file: {}", error)))?;
+
+    bytecode::load(&bytes).map_err(|error| wrong(format!("couldn't load bytecode: {}", error)))
+}
+
+fn compile(path: &str, output: Option<&str>) -> Result<(), Vec<Diagnostic>> {
+    let source = read_source(path)?;
+    let program = compile_source(&source, &machine())?;
+
+    let output = match output {
+        Some(output) => output.to_string(),
+        None => format!("{}.nlsc", path.strip_suffix(".niels").unwrap_or(path)),
+    };
+
+    fs::write(&output, bytecode::write(&program, &source.file)).map_err(|error| {
+        vec!(Diagnostic::new(
+            Wrong(format!("couldn't write file: {}", error)),
+            &FilePath(output.clone()),
+            None,
+        ))
+    })
+}
+
+fn dis(path: &str) -> Result<(), Vec<Diagnostic>> {
+    let (program, _) = load_program(path, &machine())?;
+
+    print!("{}", disassemble(&program));
+
+    Ok(())
+}
 
-    match parser.parse() {
-        Ok(ref ast) => {
-            println!("{:#?}", ast);
+fn run(path: &str, gc_stats: bool) -> Result<(), Vec<Diagnostic>> {
+    let mut vm = machine();
+
+    let (program, file) = load_program(path, &vm)?;
+
+    verify(&program).map_err(|error| vec!(error.diagnostic(&program, &file)))?;
+
+    let result = vm.execute(&program).map_err(|error| vec!(error.diagnostic(&program, &file)));
+
+    if gc_stats {
+        let stats = vm.heap.stats;
+
+        eprintln!(
+            "gc: {} collections, {} allocated, {} freed, {} live, {} at peak",
+            stats.collections, stats.allocated, stats.freed, stats.live, stats.peak
+        )
+    }
+
+    result
+}
+
+// reads one entry, continuing until a blank line once a body has been opened with `:`
+fn read_entry(stdin: &io::Stdin) -> Option<Vec<String>> {
+    let mut lines = Vec::new();
+
+    loop {
+        print!("{}", if lines.is_empty() { ">>> " } else { "... " });
+        io::stdout().flush().ok()?;
+
+        let mut line = String::new();
+
+        if stdin.lock().read_line(&mut line).ok()? == 0 {
+            return if lines.is_empty() { None } else { Some(lines) };
         }
 
-        _ => return,
+        let line = line.trim_end().to_string();
+
+        if lines.is_empty() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let opens_body = line.ends_with(':');
+
+            lines.push(line);
+
+            if !opens_body {
+                return Some(lines);
+            }
+        } else if line.trim().is_empty() {
+            return Some(lines);
+        } else {
+            lines.push(line)
+        }
     }
 }
 
-fn main() {
-    use OpCode::*;
+fn repl() -> Result<(), Vec<Diagnostic>> {
+    let stdin = io::stdin();
+    let mut emitter = Emitter::new(io::stdout());
 
-    let mut vm = VirtualMachine::new();
-    let program = [
-        // a = 10
-        LoadInt(10),
-        SetLocal(0),
+    let mut vm = machine();
+    let mut compiler = compiler(&vm);
 
-        // b = bob(a)
-        LoadLocal(0),
-        Call(6),
+    while let Some(lines) = read_entry(&stdin) {
+        let source = Source::from("<repl>", lines);
 
-        SetLocal(1),
-        LoadLocal(1),
+        let ast = match parse(&source) {
+            Ok(ast) => ast,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    emitter.emit(diagnostic)
+                }
 
+                continue;
+            },
+        };
 
-        // == function part of the program ==
-        Jmp(16), // .. jumping past it
+        let program = match compiler.compile_interactive(&ast, &source) {
+            Ok(program) => program,
+            Err(diagnostic) => {
+                emitter.emit(&diagnostic);
+                continue;
+            },
+        };
 
-        PushFrame,
-        // bob(a)
-        // declare local a
-        SetLocal(0), // notice cool local scope B-)
+        if let Err(error) = vm.execute(program) {
+            emitter.emit(&error.diagnostic(program, &source.file));
 
-        // return a * 2 + 10
-        LoadInt(2),
-        LoadLocal(0),
-        Mul,
-        LoadInt(10),
-        Add,
+            vm.unwind();
+            vm.ip = program.code.len();
 
-        PopFrame,
+            continue;
+        }
 
-        Ret
-    ];
+        if let Some(value) = vm.stack.pop() {
+            println!("{}", vm.display(&value))
+        }
+    }
 
-    vm.execute(&program);
+    println!();
 
-    println!("{:#?}", vm.stack);
-    println!("{:#?}", vm.heap);
-}
\ No newline at end of file
+    Ok(())
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let result = match args.as_slice() {
+        ["run", path] => run(path, false),
+        ["run", "--gc-stats", path] => run(path, true),
+        ["compile", path] => compile(path, None),
+        ["compile", path, "-o", output] => compile(path, Some(output)),
+        ["dis", path] => dis(path),
+        ["repl"] => repl(),
+        _ => {
+            println!("{}", Wrong(USAGE));
+            process::exit(1)
+        },
+    };
+
+    if let Err(diagnostics) = result {
+        let mut emitter = Emitter::new(io::stdout());
+
+        for diagnostic in &diagnostics {
+            emitter.emit(diagnostic)
+        }
+
+        if emitter.wrongs > 0 {
+            process::exit(1)
+        }
+    }
+}
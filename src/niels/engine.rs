@@ -0,0 +1,233 @@
+use super::error::Response::Wrong;
+use super::error::*;
+use super::interpreter::*;
+use super::parser::*;
+use super::source::*;
+
+use std::any;
+use std::path::Path;
+
+// a compiler and VM kept together, for running niels from rust:
+//
+//     let mut engine = Engine::new();
+//
+//     engine.eval::<()>("pub funk add(a, b): return a + b")?;
+//
+//     let sum: i32 = engine.call("add", (1, 2))?;
+//
+// every eval appends to the same program, so globals and funks stay around between them;
+// a `Value` handed out points into the heap only until the engine runs code again
+pub struct Engine {
+    compiler: Compiler,
+    vm: VirtualMachine,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            compiler: Compiler::new(),
+            vm: VirtualMachine::new(),
+        }
+    }
+
+    // see `VirtualMachine::register_native`; only code evaluated afterwards can call it
+    pub fn register_native(&mut self, name: &str, arity: u32, function: NativeFn) {
+        self.vm.register_native(name, arity, function);
+        self.compiler.declare_native(name, arity)
+    }
+
+    // see `Heap::set_config`; the defaults suit most scripts
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.vm.set_gc_config(config)
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.vm.heap.stats
+    }
+
+    // where `import` looks for modules, after the directory of the file importing them
+    pub fn add_search_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.compiler.add_search_path(path)
+    }
+
+    // runs `code`, giving back the value of a trailing expression, or nil
+    pub fn eval<T: FromValue>(&mut self, code: &str) -> Result<T, Vec<Diagnostic>> {
+        let source = Source::from("<eval>", code.lines().map(String::from).collect());
+
+        self.eval_source(&source)
+    }
+
+    pub fn eval_source<T: FromValue>(&mut self, source: &Source) -> Result<T, Vec<Diagnostic>> {
+        let ast = parse(source)?;
+
+        self.compiler.compile_interactive(&ast, source).map_err(|diagnostic| vec!(diagnostic))?;
+
+        self.run().map_err(|error| vec!(self.diagnostic(&error)))?;
+
+        let value = self.vm.stack.pop().unwrap_or_else(Value::nil);
+
+        self.convert(value)
+    }
+
+    // calls a `pub funk` defined by an earlier eval
+    pub fn call<A: IntoArgs, T: FromValue>(&mut self, name: &str, args: A) -> Result<T, Vec<Diagnostic>> {
+        let export = match self.compiler.program().exports.iter().find(|export| export.name == name) {
+            Some(export) => export.clone(),
+            None => return Err(self.wrong(format!("there's no `pub funk {}`", name))),
+        };
+
+        let args = args.into_args(&mut self.vm);
+
+        if args.len() != export.arity as usize {
+            return Err(self.wrong(format!("`{}` takes {} arguments, found {}", name, export.arity, args.len())));
+        }
+
+        let argc = args.len() as u32;
+
+        self.vm.reserve_globals(self.compiler.program());
+
+        self.vm.stack.push(Value::funk(export.address));
+        self.vm.stack.extend(args);
+
+        // returning to the end of the code stops the VM there
+        let end = self.compiler.program().code.len();
+
+        if let Err(kind) = self.vm.call(argc, end) {
+            self.vm.unwind();
+
+            return Err(self.wrong(kind.to_string()));
+        }
+
+        self.run().map_err(|mut error| {
+            // the outermost call is the one made from here
+            error.trace.pop();
+
+            vec!(self.diagnostic(&error))
+        })?;
+
+        let value = self.vm.stack.pop().unwrap_or_else(Value::nil);
+
+        self.convert(value)
+    }
+
+    // a top-level local, as it was left by the last eval
+    pub fn global<T: FromValue>(&self, name: &str) -> Option<T> {
+        let slot = self.compiler.global(name)?;
+
+        T::from_value(self.vm.var_stack[slot as usize], &self.vm)
+    }
+
+    pub fn display(&self, value: &Value) -> String {
+        self.vm.display(value)
+    }
+
+    fn run(&mut self) -> Result<(), RuntimeError> {
+        let program = self.compiler.program();
+
+        let result = self.vm.execute(program);
+
+        if result.is_err() {
+            self.vm.unwind();
+            self.vm.ip = program.code.len();
+        }
+
+        result
+    }
+
+    fn convert<T: FromValue>(&self, value: Value) -> Result<T, Vec<Diagnostic>> {
+        T::from_value(value, &self.vm).ok_or_else(|| {
+            self.wrong(format!("expected {}, found `{}`", any::type_name::<T>(), self.vm.display(&value)))
+        })
+    }
+
+    // the program knows which eval the failing code came from
+    fn diagnostic(&self, error: &RuntimeError) -> Diagnostic {
+        error.diagnostic(self.compiler.program(), &FilePath(String::from("<engine>")))
+    }
+
+    fn wrong(&self, message: String) -> Vec<Diagnostic> {
+        vec!(Diagnostic::new(Wrong(message), &FilePath(String::from("<engine>")), None))
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_failed_redefinition_keeps_the_old_export() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("pub funk f(): return 1").unwrap();
+
+        assert!(engine.eval::<()>("pub funk f(): return 2\nmissing").is_err());
+
+        assert_eq!(engine.call::<_, i32>("f", ()).unwrap(), 1);
+    }
+
+    #[test]
+    fn a_failed_implement_keeps_the_old_methods() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("implement P:\n    funk m(): return 1\np = P { a: 0 }").unwrap();
+
+        assert!(engine.eval::<()>("implement Shape for P:\n    funk m(): return 2\nmissing").is_err());
+
+        assert_eq!(engine.eval::<i32>("p.m()").unwrap(), 1);
+        assert!(engine.compiler.program().types[0].traits.is_empty());
+    }
+
+    fn wrong(engine: &mut Engine, code: &str) -> String {
+        match engine.eval::<()>(code).unwrap_err()[0].response {
+            Response::Wrong(ref message) => message.clone(),
+            ref other => panic!("expected an error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn implement_attaches_methods_to_records() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("implement Point:\n    funk norm():\n        return self.x + self.y\n    funk moved(dx, dy):\n        return Point { x: self.x + dx, y: self.y + dy }\n    funk shift(dx):\n        self.x = self.x + dx\n        return self\np = Point { x: 3, y: 4 }").unwrap();
+
+        assert_eq!(engine.eval::<i32>("p.norm()").unwrap(), 7);
+        assert_eq!(engine.eval::<i32>("p.moved(1, 1).norm()").unwrap(), 9);
+        assert_eq!(engine.eval::<i32>("p.shift(10).shift(1).x").unwrap(), 14);
+        assert_eq!(engine.eval::<i32>("p.x").unwrap(), 14);
+    }
+
+    #[test]
+    fn methods_see_fields_before_other_methods() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("implement Box:\n    funk f(): return 1\n    funk getter(): return || self.v\nb = Box { v: 5, f: |a| a * 2 }").unwrap();
+
+        assert_eq!(engine.eval::<i32>("b.f(21)").unwrap(), 42);
+        assert_eq!(engine.eval::<i32>("b.getter()()").unwrap(), 5);
+    }
+
+    #[test]
+    fn a_later_implement_replaces_a_method() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("implement P:\n    funk m(): return 1\nimplement Show for P:\n    funk m(): return 2").unwrap();
+
+        assert_eq!(engine.eval::<i32>("P { a: 0 }.m()").unwrap(), 2);
+    }
+
+    #[test]
+    fn bad_method_calls_are_diagnosed() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("implement P:\n    funk m(a): return a\np = P { a: 0 }").unwrap();
+
+        assert_eq!(wrong(&mut engine, "p.m(1, 2)"), "expected 1 arguments, found 2");
+        assert_eq!(wrong(&mut engine, "p.nope()"), "no field or method `nope`");
+    }
+}
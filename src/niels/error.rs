@@ -1,6 +1,11 @@
 use colored::Colorize;
 use std::fmt;
+use std::io;
 
+use super::lexer::Pos;
+use super::source::FilePath;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Response<T: fmt::Display> {
     Wrong(T),
     Weird(T),
@@ -9,16 +14,6 @@ pub enum Response<T: fmt::Display> {
 
 use self::Response::*;
 
-#[macro_export]
-macro_rules! response {
-  ( $( $r:expr ),+ ) => {{
-    $(
-        print!("{}", $r);
-    )*
-    println!();
-  }};
-}
-
 impl<T: fmt::Display> fmt::Display for Response<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (color, message_type, message) = match *self {
@@ -35,3 +30,76 @@ impl<T: fmt::Display> fmt::Display for Response<T> {
         write!(f, "{}", message)
     }
 }
+
+// how many diagnostics are collected before giving up on a file
+pub const DIAGNOSTIC_LIMIT: usize = 20;
+
+// nearly every result carries one of these as its error, so the bulky position is boxed to
+// keep that small
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub response: Response<String>,
+    pub file: FilePath,
+    pub pos: Option<Box<Pos>>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(response: Response<String>, file: &FilePath, pos: Option<Pos>) -> Self {
+        Diagnostic {
+            response,
+            file: file.clone(),
+            pos: pos.map(Box::new),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn wrong<T: fmt::Display>(message: T, file: &FilePath, pos: Pos) -> Self {
+        Self::new(Wrong(message.to_string()), file, Some(pos))
+    }
+
+    pub fn with_note<T: fmt::Display>(mut self, note: T) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn is_wrong(&self) -> bool {
+        matches!(self.response, Wrong(_))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.response, self.file)?;
+
+        if let Some(ref pos) = self.pos {
+            write!(f, "{}", pos)?
+        }
+
+        for note in &self.notes {
+            write!(f, "{}", Note(note))?
+        }
+
+        Ok(())
+    }
+}
+
+// renders diagnostics to a writer, keeping count of the wrong ones
+pub struct Emitter<W: io::Write> {
+    out: W,
+    pub wrongs: usize,
+}
+
+impl<W: io::Write> Emitter<W> {
+    pub fn new(out: W) -> Self {
+        Emitter { out, wrongs: 0 }
+    }
+
+    pub fn emit(&mut self, diagnostic: &Diagnostic) {
+        if diagnostic.is_wrong() {
+            self.wrongs += 1
+        }
+
+        let _ = writeln!(self.out, "{}", diagnostic);
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+
+// a position in the code that jumps can target before it's known
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Label(usize);
+
+// a jump waiting for its label to be bound
+struct Fixup {
+    offset: usize,
+    op: fn(u32) -> OpCode,
+    label: Label,
+}
+
+// builds a `Program`, resolving labels and interning constants on the way:
+//
+//     let mut asm = Assembler::new();
+//     let done = asm.label();
+//
+//     asm.load_string("hello");
+//     asm.jump(OpCode::Jmp, done);
+//     asm.emit(OpCode::LoadNil);
+//     asm.bind(done);
+//
+//     let program = asm.program;
+#[derive(Default)]
+pub struct Assembler {
+    pub program: Program,
+
+    labels: Vec<Option<u32>>,
+    fixups: Vec<Fixup>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler::default()
+    }
+
+    pub fn emit(&mut self, op: OpCode) -> usize {
+        self.program.push(op)
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+
+        Label(self.labels.len() - 1)
+    }
+
+    // a label bound to the next instruction
+    pub fn here(&mut self) -> Label {
+        let label = self.label();
+
+        self.bind(label);
+
+        label
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        let address = self.program.code.len() as u32;
+
+        self.labels[label.0] = Some(address);
+
+        let (ready, waiting): (Vec<Fixup>, _) = self.fixups.drain(..).partition(|fixup| fixup.label == label);
+
+        self.fixups = waiting;
+
+        for fixup in ready {
+            self.program.patch(fixup.offset, (fixup.op)(address))
+        }
+    }
+
+    pub fn address(&self, label: Label) -> Option<u32> {
+        self.labels[label.0]
+    }
+
+    // emits a jump or anything else taking an address, like `asm.jump(OpCode::LoadFunk, f)`
+    pub fn jump(&mut self, op: fn(u32) -> OpCode, label: Label) -> usize {
+        match self.address(label) {
+            Some(address) => self.emit(op(address)),
+            None => {
+                let offset = self.emit(op(0));
+
+                self.fixups.push(Fixup { offset, op, label });
+
+                offset
+            },
+        }
+    }
+
+    pub fn load_float(&mut self, a: f64) -> usize {
+        let index = self.program.intern(Constant::Float(a));
+
+        self.emit(OpCode::LoadFloat(index))
+    }
+
+    pub fn load_string(&mut self, a: &str) -> usize {
+        let index = self.program.intern(Constant::Str(a.to_string()));
+
+        self.emit(OpCode::LoadString(index))
+    }
+
+    pub fn load_record(&mut self, names: Vec<String>) -> usize {
+        let index = self.program.intern(Constant::Names(names));
+
+        self.emit(OpCode::LoadRecord(index))
+    }
+
+    pub fn load_field(&mut self, name: &str) -> usize {
+        let index = self.program.intern(Constant::Str(name.to_string()));
+
+        self.emit(OpCode::LoadField(index))
+    }
+
+    pub fn set_field(&mut self, name: &str) -> usize {
+        let index = self.program.intern(Constant::Str(name.to_string()));
+
+        self.emit(OpCode::SetField(index))
+    }
+
+    pub fn call_native(&mut self, name: &str, arity: u32) -> usize {
+        let index = self.program.intern(Constant::Native(name.to_string(), arity));
+
+        self.emit(OpCode::CallNative(index))
+    }
+
+    pub fn invoke(&mut self, name: &str, argc: u32) -> usize {
+        let index = self.program.intern(Constant::Method(name.to_string(), argc));
+
+        self.emit(OpCode::Invoke(index))
+    }
+
+    pub fn instance(&mut self, ty: &str) -> usize {
+        let index = self.program.intern(Constant::Str(ty.to_string()));
+
+        self.emit(OpCode::Instance(index))
+    }
+
+    // turns the funk on top of the stack into a closure over `captures`
+    pub fn closure(&mut self, captures: Vec<Capture>) -> usize {
+        let index = self.program.intern(Constant::Captures(captures));
+
+        self.emit(OpCode::Closure(index))
+    }
+
+    // drops the code from `len` on, along with any jumps in it still waiting for a label
+    pub fn truncate(&mut self, len: usize) {
+        self.program.truncate(len);
+        self.fixups.retain(|fixup| fixup.offset < len)
+    }
+}
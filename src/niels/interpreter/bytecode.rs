@@ -0,0 +1,532 @@
+use super::*;
+
+use std::fmt;
+
+// the `.nlsc` format, all integers little-endian and strings as a `u32` length and UTF-8:
+//
+//     magic      b"NLSC"
+//     version    u16
+//     source     string, the file the program was compiled from
+//     constants  u32 count, then per constant a tag byte and its payload
+//     code       u32 length, then the packed instructions
+//     lines      u32 count, then per entry the offset, line number, line, start and end
+//     files      u32 count, then per entry the offset and file
+//     exports    u32 count, then per `pub funk` its name, address and arity
+//     types      u32 count, then per type its name, its traits as a u32 count and names, and
+//                its methods as a u32 count and each one's name, address and arity
+//     globals    u32, the slots taken by top-level locals
+const MAGIC: &[u8; 4] = b"NLSC";
+
+pub const VERSION: u16 = 1;
+
+const FLOAT: u8 = 0;
+const STR: u8 = 1;
+const NAMES: u8 = 2;
+const NATIVE: u8 = 3;
+const CAPTURES: u8 = 4;
+const METHOD: u8 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Truncated,
+    Malformed(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::LoadError::*;
+
+        match *self {
+            NotBytecode => write!(f, "not a niels bytecode file"),
+            UnsupportedVersion(version) => {
+                write!(f, "bytecode version {} isn't supported, expected {}", version, VERSION)
+            },
+            Truncated => write!(f, "bytecode file ends too early"),
+            Malformed(what) => write!(f, "malformed bytecode: {}", what),
+        }
+    }
+}
+
+pub fn write(program: &Program, file: &FilePath) -> Vec<u8> {
+    let mut out = Writer(MAGIC.to_vec());
+
+    out.u16(VERSION);
+    out.str(&file.0);
+
+    out.u32(program.constants.len() as u32);
+
+    for constant in &program.constants {
+        match *constant {
+            Constant::Float(a) => {
+                out.u8(FLOAT);
+                out.u64(a.to_bits())
+            },
+
+            Constant::Str(ref content) => {
+                out.u8(STR);
+                out.str(content)
+            },
+
+            Constant::Names(ref names) => {
+                out.u8(NAMES);
+                out.u32(names.len() as u32);
+
+                for name in names {
+                    out.str(name)
+                }
+            },
+
+            Constant::Native(ref name, arity) => {
+                out.u8(NATIVE);
+                out.str(name);
+                out.u32(arity)
+            },
+
+            Constant::Captures(ref captures) => {
+                out.u8(CAPTURES);
+                out.u32(captures.len() as u32);
+
+                for capture in captures {
+                    match *capture {
+                        Capture::Local(slot) => {
+                            out.u8(0);
+                            out.u32(slot)
+                        },
+
+                        Capture::Upvalue(index) => {
+                            out.u8(1);
+                            out.u32(index)
+                        },
+                    }
+                }
+            },
+
+            Constant::Method(ref name, argc) => {
+                out.u8(METHOD);
+                out.str(name);
+                out.u32(argc)
+            },
+        }
+    }
+
+    out.u32(program.code.len() as u32);
+    out.0.extend_from_slice(&program.code);
+
+    out.u32(program.positions.len() as u32);
+
+    for &(offset, Pos((line, ref content), (start, end))) in &program.positions {
+        out.u32(offset as u32);
+        out.u32(line as u32);
+        out.str(content);
+        out.u32(start as u32);
+        out.u32(end as u32);
+    }
+
+    out.u32(program.files.len() as u32);
+
+    for &(offset, ref file) in &program.files {
+        out.u32(offset as u32);
+        out.str(file);
+    }
+
+    out.u32(program.exports.len() as u32);
+
+    for export in &program.exports {
+        out.str(&export.name);
+        out.u32(export.address);
+        out.u32(export.arity);
+    }
+
+    out.u32(program.types.len() as u32);
+
+    for ty in &program.types {
+        out.str(&ty.name);
+        out.u32(ty.traits.len() as u32);
+
+        for name in &ty.traits {
+            out.str(name)
+        }
+
+        out.u32(ty.methods.len() as u32);
+
+        for method in &ty.methods {
+            out.str(&method.name);
+            out.u32(method.address);
+            out.u32(method.arity);
+        }
+    }
+
+    out.u32(program.globals);
+
+    out.0
+}
+
+// reads a program back, checking it's well-formed enough to disassemble or verify
+pub fn load(bytes: &[u8]) -> Result<(Program, FilePath), LoadError> {
+    use self::LoadError::*;
+
+    if !bytes.starts_with(MAGIC) {
+        return Err(NotBytecode);
+    }
+
+    let mut input = Reader { bytes, offset: MAGIC.len() };
+
+    let version = input.u16()?;
+
+    if version != VERSION {
+        return Err(UnsupportedVersion(version));
+    }
+
+    let file = FilePath(input.str()?);
+
+    let mut program = Program::default();
+
+    for _ in 0 .. input.u32()? {
+        let constant = match input.u8()? {
+            FLOAT => Constant::Float(f64::from_bits(input.u64()?)),
+            STR => Constant::Str(input.str()?),
+            NAMES => {
+                let mut names = Vec::new();
+
+                for _ in 0 .. input.u32()? {
+                    names.push(input.str()?)
+                }
+
+                Constant::Names(names)
+            },
+            NATIVE => Constant::Native(input.str()?, input.u32()?),
+            CAPTURES => {
+                let mut captures = Vec::new();
+
+                for _ in 0 .. input.u32()? {
+                    let capture = match input.u8()? {
+                        0 => Capture::Local(input.u32()?),
+                        1 => Capture::Upvalue(input.u32()?),
+                        _ => return Err(Malformed("unknown kind of capture")),
+                    };
+
+                    captures.push(capture)
+                }
+
+                Constant::Captures(captures)
+            },
+            METHOD => Constant::Method(input.str()?, input.u32()?),
+            _ => return Err(Malformed("unknown kind of constant")),
+        };
+
+        program.constants.push(constant)
+    }
+
+    let len = input.u32()? as usize;
+
+    program.code = input.take(len)?.to_vec();
+
+    let mut offset = 0;
+
+    while offset < program.code.len() {
+        match program.decode(offset) {
+            Some((_, len)) => offset += len,
+            None => return Err(Malformed("undecodable instruction")),
+        }
+    }
+
+    for _ in 0 .. input.u32()? {
+        let offset = input.u32()? as usize;
+        let line = input.u32()? as usize;
+        let content = input.str()?;
+        let slice = (input.u32()? as usize, input.u32()? as usize);
+
+        if offset > program.code.len() || program.positions.last().map(|&(last, _)| last >= offset).unwrap_or(false) {
+            return Err(Malformed("line table out of order"));
+        }
+
+        // `Pos` tolerates pointing just past its line when displayed, but nothing further, as
+        // it pads its carets out to the start
+        let end = slice.1.min(content.len());
+
+        if slice.0 > content.len() + 1
+            || slice.1 > content.len() + 1
+            || !content.is_char_boundary(slice.0.saturating_sub(1).min(end))
+            || !content.is_char_boundary(end)
+        {
+            return Err(Malformed("position outside of its line"));
+        }
+
+        program.positions.push((offset, Pos((line, content), slice)))
+    }
+
+    for _ in 0 .. input.u32()? {
+        let offset = input.u32()? as usize;
+        let file = input.str()?;
+
+        if offset > program.code.len() || program.files.last().map(|&(last, _)| last >= offset).unwrap_or(false) {
+            return Err(Malformed("file table out of order"));
+        }
+
+        program.files.push((offset, file))
+    }
+
+    for _ in 0 .. input.u32()? {
+        let export = Export {
+            name: input.str()?,
+            address: input.u32()?,
+            arity: input.u32()?,
+        };
+
+        if export.address as usize >= program.code.len() {
+            return Err(Malformed("export outside of the code"));
+        }
+
+        program.exports.push(export)
+    }
+
+    for _ in 0 .. input.u32()? {
+        let mut ty = Type {
+            name: input.str()?,
+            traits: Vec::new(),
+            methods: Vec::new(),
+        };
+
+        for _ in 0 .. input.u32()? {
+            ty.traits.push(input.str()?)
+        }
+
+        for _ in 0 .. input.u32()? {
+            let method = Method {
+                name: input.str()?,
+                address: input.u32()?,
+                arity: input.u32()?,
+            };
+
+            if method.address as usize >= program.code.len() {
+                return Err(Malformed("method outside of the code"));
+            }
+
+            ty.methods.push(method)
+        }
+
+        program.types.push(ty)
+    }
+
+    program.globals = input.u32()?;
+
+    if program.globals as usize > LOCAL_SLOTS {
+        return Err(Malformed("more globals than local slots"));
+    }
+
+    if input.offset != bytes.len() {
+        return Err(Malformed("trailing bytes"));
+    }
+
+    Ok((program, file))
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, a: u8) {
+        self.0.push(a)
+    }
+
+    fn u16(&mut self, a: u16) {
+        self.0.extend_from_slice(&a.to_le_bytes())
+    }
+
+    fn u32(&mut self, a: u32) {
+        self.0.extend_from_slice(&a.to_le_bytes())
+    }
+
+    fn u64(&mut self, a: u64) {
+        self.0.extend_from_slice(&a.to_le_bytes())
+    }
+
+    fn str(&mut self, a: &str) {
+        self.u32(a.len() as u32);
+        self.0.extend_from_slice(a.as_bytes())
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], LoadError> {
+        let end = self.offset.checked_add(len).ok_or(LoadError::Truncated)?;
+        let bytes = self.bytes.get(self.offset .. end).ok_or(LoadError::Truncated)?;
+
+        self.offset = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let bytes = self.take(4)?;
+
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        let mut bytes = [0; 8];
+
+        bytes.copy_from_slice(self.take(8)?);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn str(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()? as usize;
+
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| LoadError::Malformed("string isn't UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niels::parser::parse;
+
+    // a program using every kind of constant and table
+    fn sample() -> (Program, FilePath) {
+        let code = "
+pub funk adder(a):
+    return |b| a + b
+
+implement Point:
+    funk sum():
+        return self.x + self.y
+
+point = Point { x: 1.5, y: 2 }
+print(\"{point.sum()}\")
+";
+
+        let source = Source::from("sample.niels", code.lines().map(String::from).collect());
+        let ast = parse(&source).unwrap();
+
+        let mut compiler = Compiler::new();
+
+        compiler.declare_native("print", 1);
+
+        (compiler.compile(&ast, &source).unwrap().clone(), source.file)
+    }
+
+    fn error(bytes: &[u8]) -> Option<LoadError> {
+        load(bytes).err()
+    }
+
+    #[test]
+    fn round_trips() {
+        let (program, file) = sample();
+
+        let (loaded, loaded_file) = load(&write(&program, &file)).unwrap();
+
+        assert_eq!(loaded, program);
+        assert_eq!(loaded_file.0, file.0);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let (program, file) = sample();
+        let bytes = write(&program, &file);
+
+        for len in MAGIC.len() .. bytes.len() {
+            assert_eq!(error(&bytes[.. len]), Some(LoadError::Truncated), "cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn magic_and_version_are_checked() {
+        let (program, file) = sample();
+        let mut bytes = write(&program, &file);
+
+        bytes[4 .. 6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert_eq!(error(&bytes), Some(LoadError::UnsupportedVersion(VERSION + 1)));
+
+        bytes[0] = b'X';
+
+        assert_eq!(error(&bytes), Some(LoadError::NotBytecode));
+    }
+
+    #[test]
+    fn unknown_constants_are_rejected() {
+        let (program, file) = sample();
+        let mut bytes = write(&program, &file);
+
+        // the first constant's tag follows the magic, version, source and constant count
+        let tag = MAGIC.len() + 2 + 4 + file.0.len() + 4;
+
+        bytes[tag] = 0xff;
+
+        assert_eq!(error(&bytes), Some(LoadError::Malformed("unknown kind of constant")));
+    }
+
+    #[test]
+    fn line_tables_have_to_be_in_order() {
+        let mut program = Program::default();
+        let pos = Pos((1, String::from("1")), (1, 1));
+
+        program.push(OpCode::LoadInt(1));
+        program.push(OpCode::Pop);
+
+        program.positions = vec!((5, pos.clone()), (0, pos));
+
+        let bytes = write(&program, &FilePath(String::from("<test>")));
+
+        assert_eq!(error(&bytes), Some(LoadError::Malformed("line table out of order")));
+    }
+
+    #[test]
+    fn positions_have_to_stay_on_their_line() {
+        let mut program = Program::default();
+
+        program.push(OpCode::LoadNil);
+        program.positions = vec!((0, Pos((1, String::from("nil")), (u32::MAX as usize, 3))));
+
+        let bytes = write(&program, &FilePath(String::from("<test>")));
+
+        assert_eq!(error(&bytes), Some(LoadError::Malformed("position outside of its line")));
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let (program, file) = sample();
+        let mut bytes = write(&program, &file);
+
+        bytes.push(0);
+
+        assert_eq!(error(&bytes), Some(LoadError::Malformed("trailing bytes")));
+    }
+
+    #[test]
+    fn corrupted_files_load_or_fail_without_panicking() {
+        let (program, file) = sample();
+        let bytes = write(&program, &file);
+
+        for at in 0 .. bytes.len() {
+            for flip in &[0x01, 0x80, 0xff] {
+                let mut corrupted = bytes.clone();
+
+                corrupted[at] ^= flip;
+
+                // anything that loads is handed to the verifier next, which mustn't panic either
+                if let Ok((program, _)) = load(&corrupted) {
+                    let _ = verify(&program);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1306 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+struct Function {
+    label: Label,
+    arity: usize,
+}
+
+#[derive(Clone)]
+struct Loop {
+    start: Label, // where `skip` jumps to
+    exit: Label,  // where `break` jumps to
+}
+
+// one per `funk` body, the outermost being the top-level program
+#[derive(Default, Clone)]
+struct Scope {
+    locals: HashMap<String, u32>,
+    functions: HashMap<String, usize>,
+    loops: Vec<Loop>,
+
+    // what the funk's closure captures from the enclosing funks, see `OpCode::Closure`
+    upvalues: Vec<Capture>,
+
+    // names made `pub`, and modules imported whole by the name they're used with; only the
+    // top level has either
+    public: Vec<String>,
+    modules: HashMap<String, usize>,
+}
+
+// where a name used in a funk lives
+#[derive(Clone, Copy)]
+enum Variable {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32), // a top-level local, which is never captured since it outlives every funk
+}
+
+// a compiled file, and the items it made `pub`
+struct Module {
+    path: PathBuf, // canonical, so one file is one module however it's reached
+    items: HashMap<String, Item>,
+}
+
+#[derive(Clone, Copy)]
+enum Item {
+    Funk(usize),
+    Global(u32),
+}
+
+pub struct Compiler {
+    file: FilePath,
+
+    asm: Assembler,
+    scopes: Vec<Scope>,
+
+    // positions of the statements and expressions being compiled, innermost last
+    positions: Vec<Pos>,
+
+    functions: Vec<Function>,
+
+    // funks the host provides, by name; those in the script take precedence
+    natives: HashMap<String, u32>,
+
+    // the top-level locals of every module share the slots below the VM's locals, these being
+    // the ones taken so far
+    globals: u32,
+
+    // where modules not found next to the importing file are looked for
+    search_path: Vec<PathBuf>,
+
+    modules: Vec<Module>,
+
+    // the files being compiled and what they're called, the one importing the rest first
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            file: FilePath(String::new()),
+
+            asm: Assembler::new(),
+            scopes: vec!(Scope::default()),
+
+            positions: Vec::new(),
+
+            functions: Vec::new(),
+
+            natives: HashMap::new(),
+
+            globals: 0,
+
+            search_path: Vec::new(),
+
+            modules: Vec::new(),
+
+            loading: Vec::new(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.asm.program
+    }
+
+    // the slot of a top-level local
+    pub fn global(&self, name: &str) -> Option<u32> {
+        self.scopes[0].locals.get(name).cloned()
+    }
+
+    // lets scripts call a native registered with `VirtualMachine::register_native`
+    pub fn declare_native(&mut self, name: &str, arity: u32) {
+        self.natives.insert(name.to_string(), arity);
+    }
+
+    pub fn add_search_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.search_path.push(path.as_ref().to_path_buf())
+    }
+
+    pub fn compile(&mut self, ast: &[Statement], source: &Source) -> Result<&Program, Diagnostic> {
+        self.compile_with(ast, None, source)
+    }
+
+    // like `compile`, but a trailing expression statement leaves its value on the stack
+    pub fn compile_interactive(&mut self, ast: &[Statement], source: &Source) -> Result<&Program, Diagnostic> {
+        match ast.split_last() {
+            Some((&Statement { node: StatementNode::Expression(ref expression), .. }, rest)) => {
+                self.compile_with(rest, Some(expression), source)
+            },
+
+            _ => self.compile_with(ast, None, source),
+        }
+    }
+
+    // code compiled by earlier calls is kept, so a long-lived compiler can keep appending to
+    // the same program; a failed call leaves everything as it was
+    fn compile_with(&mut self, ast: &[Statement], result: Option<&Expression>, source: &Source) -> Result<&Program, Diagnostic> {
+        let code_len = self.asm.program.code.len();
+        let functions_len = self.functions.len();
+        let modules_len = self.modules.len();
+        let types = self.asm.program.types.clone();
+        let exports = self.asm.program.exports.clone();
+        let globals = self.globals;
+        let top_scope = self.scopes[0].clone();
+
+        self.file = source.file.clone();
+
+        // a file that isn't one, like the repl's, can't be imported anyway
+        let path = fs::canonicalize(&source.file.0).unwrap_or_else(|_| PathBuf::from(&source.file.0));
+
+        self.loading = vec!((path, source.file.0.clone()));
+
+        self.asm.program.locate_file(&source.file.0);
+
+        let compiled = self.compile_block(ast).and_then(|_| match result {
+            Some(expression) => self.compile_expression(expression),
+            None => Ok(()),
+        });
+
+        if let Err(diagnostic) = compiled {
+            self.asm.truncate(code_len);
+            self.positions.clear();
+            self.functions.truncate(functions_len);
+            self.modules.truncate(modules_len);
+
+            // a failed redefinition has to give the old funks back
+            self.asm.program.exports = exports;
+            self.asm.program.types = types;
+
+            self.globals = globals;
+            self.asm.program.globals = globals;
+            self.scopes = vec!(top_scope);
+
+            return Err(diagnostic);
+        }
+
+        Ok(&self.asm.program)
+    }
+
+    fn compile_block(&mut self, block: &[Statement]) -> Result<(), Diagnostic> {
+        // functions are visible to the whole block, so calls may come before the definition
+        for statement in block {
+            self.declare_function(statement)
+        }
+
+        for statement in block {
+            self.compile_statement(statement)?
+        }
+
+        Ok(())
+    }
+
+    // only top-level funks are hoisted, those in a funk body are locals like any other; types
+    // are too, so records can be made before their `implement` block
+    fn declare_function(&mut self, statement: &Statement) {
+        use self::StatementNode::*;
+
+        match statement.node {
+            Function(ref name, ref params, _) if self.scopes.len() == 1 => {
+                let id = self.functions.len();
+                let label = self.asm.label();
+
+                self.functions.push(self::Function {
+                    label,
+                    arity: params.len(),
+                });
+
+                self.scope_mut().functions.insert(name.clone(), id);
+            },
+
+            Public(ref statement) => self.declare_function(statement),
+
+            Implement(ref name, ..) if self.scopes.len() == 1 => {
+                self.asm.program.declare_type(name);
+            },
+
+            _ => (),
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        self.enter(&statement.pos);
+
+        let result = self.compile_statement_node(statement);
+
+        self.leave();
+
+        result
+    }
+
+    fn compile_statement_node(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        use self::StatementNode::*;
+
+        match statement.node {
+            Expression(ref expression) => {
+                self.compile_expression(expression)?;
+                self.emit(OpCode::Pop);
+            },
+
+            Assignment(ref target, ref value) => self.compile_assignment(target, value)?,
+
+            Return(ref value) => {
+                if self.scopes.len() == 1 {
+                    return Err(Diagnostic::wrong(
+                        "can't return outside of a funk",
+                        &self.file,
+                        statement.pos.clone())
+                    );
+                }
+
+                if let Some(ref value) = *value {
+                    self.compile_expression(value)?
+                } else {
+                    self.emit(OpCode::LoadNil);
+                }
+
+                self.emit(OpCode::Ret);
+            },
+
+            // the local is there before the closure is made, so the funk can call itself
+            Function(ref name, ref params, ref body) if self.scopes.len() > 1 => {
+                let slot = self.local(name);
+
+                self.compile_closure(params, |compiler| compiler.compile_funk_body(body))?;
+
+                self.emit(OpCode::SetLocal(slot));
+            },
+
+            Function(ref name, ref params, ref body) => {
+                let id = self.scope().functions[name];
+
+                let skip = self.asm.label();
+
+                self.asm.jump(OpCode::Jmp, skip);
+                self.asm.bind(self.functions[id].label);
+
+                // all a top-level funk can see outside itself are globals
+                self.compile_funk(params, |compiler| compiler.compile_funk_body(body))?;
+
+                self.asm.bind(skip)
+            },
+
+            If(ref condition, ref body, ref elifs, ref else_body) => {
+                let end = self.asm.label();
+
+                let branches = Some((condition, body)).into_iter()
+                    .chain(elifs.iter().map(|(condition, body)| (condition, body)));
+
+                for (condition, body) in branches {
+                    self.compile_expression(condition)?;
+
+                    let next = self.asm.label();
+
+                    self.asm.jump(OpCode::JmpIfNot, next);
+
+                    self.compile_block(body)?;
+
+                    self.asm.jump(OpCode::Jmp, end);
+                    self.asm.bind(next)
+                }
+
+                if let Some(ref body) = *else_body {
+                    self.compile_block(body)?
+                }
+
+                self.asm.bind(end)
+            },
+
+            While(ref condition, ref body) => {
+                let start = self.asm.here();
+                let exit = self.asm.label();
+
+                self.compile_expression(condition)?;
+
+                self.asm.jump(OpCode::JmpIfNot, exit);
+
+                self.compile_loop(start, exit, body)?
+            },
+
+            // the sequence and a counter live in hidden locals, named so they can't clash
+            For(ref name, ref iterable, ref body) => {
+                let id = self.asm.program.code.len();
+
+                let sequence = self.local(&format!("<sequence {}>", id));
+                let counter  = self.local(&format!("<counter {}>", id));
+
+                self.compile_expression(iterable)?;
+                self.emit(OpCode::SetLocal(sequence));
+
+                self.emit(OpCode::LoadInt(0));
+                self.emit(OpCode::SetLocal(counter));
+
+                let start = self.asm.here();
+                let exit = self.asm.label();
+
+                self.emit(OpCode::LoadLocal(sequence));
+                self.emit(OpCode::LoadLocal(counter));
+
+                self.asm.jump(OpCode::Next, exit);
+
+                let slot = self.local(name);
+
+                self.emit(OpCode::SetLocal(slot));
+
+                self.emit(OpCode::LoadLocal(counter));
+                self.emit(OpCode::LoadInt(1));
+                self.emit(OpCode::Add);
+                self.emit(OpCode::SetLocal(counter));
+
+                self.compile_loop(start, exit, body)?
+            },
+
+            Break => {
+                let exit = match self.scope().loops.last() {
+                    Some(current) => current.exit,
+                    None => {
+                        return Err(Diagnostic::wrong(
+                            "can't break outside of a loop",
+                            &self.file,
+                            statement.pos.clone())
+                        )
+                    },
+                };
+
+                self.asm.jump(OpCode::Jmp, exit);
+            },
+
+            Skip => {
+                let start = match self.scope().loops.last() {
+                    Some(current) => current.start,
+                    None => {
+                        return Err(Diagnostic::wrong(
+                            "can't skip outside of a loop",
+                            &self.file,
+                            statement.pos.clone())
+                        )
+                    },
+                };
+
+                self.asm.jump(OpCode::Jmp, start);
+            },
+
+            Implement(ref name, ref trait_name, ref body) => {
+                if self.scopes.len() > 1 {
+                    return Err(Diagnostic::wrong(
+                        "can only implement at the top level of a file",
+                        &self.file,
+                        statement.pos.clone())
+                    );
+                }
+
+                let ty = self.asm.program.declare_type(name);
+
+                if let Some(ref trait_name) = *trait_name {
+                    let traits = &mut self.asm.program.types[ty].traits;
+
+                    if !traits.contains(trait_name) {
+                        traits.push(trait_name.clone())
+                    }
+                }
+
+                for statement in body {
+                    self.enter(&statement.pos);
+
+                    let result = self.compile_method(ty, statement);
+
+                    self.leave();
+
+                    result?
+                }
+            },
+
+            // a module's code is emitted where it's first imported, and has to run before anything
+            // uses it; so not in a funk, or in a branch or loop that might not
+            Import(ref path, ref names) => {
+                if self.scopes.len() > 1 || self.positions.len() > 1 {
+                    return Err(Diagnostic::wrong(
+                        "can only import at the top level of a file",
+                        &self.file,
+                        statement.pos.clone())
+                    );
+                }
+
+                self.import(path, names, &statement.pos)?
+            },
+
+            Public(ref statement) => {
+                self.compile_statement(statement)?;
+
+                if self.scopes.len() == 1 {
+                    let name = match statement.node {
+                        Function(ref name, ..) => Some(name),
+                        Assignment(ref target, _) => match target.node {
+                            ExpressionNode::Identifier(ref name) => Some(name),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    match name {
+                        Some(name) => self.scope_mut().public.push(name.clone()),
+                        None => {
+                            return Err(Diagnostic::wrong(
+                                "only funks and variables can be `pub`",
+                                &self.file,
+                                statement.pos.clone())
+                            )
+                        },
+                    }
+                }
+
+                // the file being compiled exports its funks, the modules it imports don't
+                if let (Function(ref name, ref params, _), 1, 1) = (&statement.node, self.scopes.len(), self.loading.len()) {
+                    let id = self.scope().functions[name];
+
+                    let export = Export {
+                        name: name.clone(),
+                        address: self.asm.address(self.functions[id].label).unwrap(),
+                        arity: params.len() as u32,
+                    };
+
+                    let exports = &mut self.asm.program.exports;
+
+                    exports.retain(|old| old.name != *name);
+                    exports.push(export)
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    // a method is compiled like a top-level funk, with `self` before its parameters
+    fn compile_method(&mut self, ty: usize, statement: &Statement) -> Result<(), Diagnostic> {
+        let (name, params, body) = match statement.node {
+            StatementNode::Function(ref name, ref params, ref body) => (name, params, body),
+            _ => {
+                return Err(Diagnostic::wrong(
+                    "only funks can go in an `implement` block",
+                    &self.file,
+                    statement.pos.clone())
+                )
+            },
+        };
+
+        let params = iter::once(String::from("self")).chain(params.iter().cloned()).collect::<Vec<String>>();
+
+        let skip = self.asm.label();
+
+        self.asm.jump(OpCode::Jmp, skip);
+
+        let start = self.asm.here();
+
+        self.compile_funk(&params, |compiler| compiler.compile_funk_body(body))?;
+
+        self.asm.bind(skip);
+
+        let method = Method {
+            name: name.clone(),
+            address: self.asm.address(start).unwrap(),
+            arity: params.len() as u32,
+        };
+
+        // a later block replaces a method, like a later `pub funk` replaces an export
+        let methods = &mut self.asm.program.types[ty].methods;
+
+        methods.retain(|old| old.name != method.name);
+        methods.push(method);
+
+        Ok(())
+    }
+
+    fn compile_funk_body(&mut self, body: &[Statement]) -> Result<(), Diagnostic> {
+        self.compile_block(body)?;
+
+        self.emit(OpCode::LoadNil);
+        self.emit(OpCode::Ret);
+
+        Ok(())
+    }
+
+    // compiles a funk in a scope of its own, giving back what it captured from the enclosing ones
+    fn compile_funk<F>(&mut self, params: &[String], body: F) -> Result<Vec<Capture>, Diagnostic>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Diagnostic>,
+    {
+        self.scopes.push(Scope::default());
+
+        self.emit(OpCode::Enter(params.len() as u32));
+
+        // the call leaves the arguments in the first locals, in order
+        for param in params {
+            self.local(param);
+        }
+
+        body(self)?;
+
+        Ok(self.scopes.pop().unwrap().upvalues)
+    }
+
+    // compiles a funk in place, leaving it on the stack; it's only made a closure if it
+    // captures anything
+    fn compile_closure<F>(&mut self, params: &[String], body: F) -> Result<(), Diagnostic>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Diagnostic>,
+    {
+        let skip = self.asm.label();
+        let start = self.asm.label();
+
+        self.asm.jump(OpCode::Jmp, skip);
+        self.asm.bind(start);
+
+        let captures = self.compile_funk(params, body)?;
+
+        self.asm.bind(skip);
+        self.asm.jump(OpCode::LoadFunk, start);
+
+        if !captures.is_empty() {
+            self.asm.closure(captures);
+        }
+
+        Ok(())
+    }
+
+    fn import(&mut self, path: &str, names: &[String], pos: &Pos) -> Result<(), Diagnostic> {
+        let id = self.load_module(path, pos)?;
+
+        if names.is_empty() {
+            let name = path.rsplit('.').next().unwrap_or(path);
+
+            self.scopes[0].modules.insert(name.to_string(), id);
+
+            return Ok(());
+        }
+
+        for name in names {
+            match self.item(id, path, name, pos)? {
+                Item::Funk(function) => {
+                    self.scopes[0].functions.insert(name.clone(), function);
+                },
+
+                Item::Global(slot) => {
+                    self.scopes[0].locals.insert(name.clone(), slot);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // compiles the module `path` the first time it's imported, giving back its index
+    fn load_module(&mut self, path: &str, pos: &Pos) -> Result<usize, Diagnostic> {
+        let wrong = |file: &FilePath, message: String| Diagnostic::wrong(message, file, pos.clone());
+
+        let found = match self.find_module(path) {
+            Some(found) => found,
+            None => return Err(wrong(&self.file, format!("can't find module `{}`", path))),
+        };
+
+        let canonical = fs::canonicalize(&found)
+            .map_err(|error| wrong(&self.file, format!("couldn't read module `{}`: {}", path, error)))?;
+
+        if let Some(id) = self.modules.iter().position(|module| module.path == canonical) {
+            return Ok(id);
+        }
+
+        if let Some(start) = self.loading.iter().position(|(loading, _)| *loading == canonical) {
+            let cycle = self.loading[start ..].iter().map(|(_, name)| name.as_str()).collect::<Vec<&str>>();
+
+            return Err(wrong(&self.file, format!("import cycle: {} -> {}", cycle.join(" -> "), cycle[0])));
+        }
+
+        let source = Source::new(found.to_string_lossy().into_owned())
+            .map_err(|error| wrong(&self.file, format!("couldn't read module `{}`: {}", path, error)))?;
+
+        let imported_here = format!("in module `{}`, imported here{}{}", path, self.file, pos);
+
+        let ast = parse(&source).map_err(|mut diagnostics| diagnostics.swap_remove(0).with_note(&imported_here))?;
+
+        // the module gets a top level of its own, and is compiled right here
+        self.loading.push((canonical.clone(), source.file.0.clone()));
+
+        let file = mem::replace(&mut self.file, source.file.clone());
+        let scopes = mem::replace(&mut self.scopes, vec!(Scope::default()));
+        let positions = mem::take(&mut self.positions);
+
+        self.asm.program.locate_file(&source.file.0);
+
+        let compiled = self.compile_block(&ast);
+
+        let top = mem::replace(&mut self.scopes, scopes).swap_remove(0);
+
+        self.asm.program.locate_file(&file.0);
+
+        self.file = file;
+        self.positions = positions;
+        self.loading.pop();
+
+        compiled.map_err(|diagnostic| diagnostic.with_note(&imported_here))?;
+
+        let mut items = HashMap::new();
+
+        for name in top.public {
+            if let Some(&function) = top.functions.get(&name) {
+                items.insert(name, Item::Funk(function));
+            } else if let Some(&slot) = top.locals.get(&name) {
+                items.insert(name, Item::Global(slot));
+            }
+        }
+
+        self.modules.push(Module {
+            path: canonical,
+            items,
+        });
+
+        Ok(self.modules.len() - 1)
+    }
+
+    // `foo.bar` is `foo/bar.niels`, next to the importing file or in a search path directory
+    fn find_module(&self, path: &str) -> Option<PathBuf> {
+        let relative = path.split('.').collect::<PathBuf>().with_extension("niels");
+        let here = Path::new(&self.file.0).parent().map(Path::to_path_buf).unwrap_or_default();
+
+        iter::once(here)
+            .chain(self.search_path.iter().cloned())
+            .map(|directory| directory.join(&relative))
+            .find(|file| file.is_file())
+    }
+
+    fn item(&self, id: usize, module: &str, name: &str, pos: &Pos) -> Result<Item, Diagnostic> {
+        match self.modules[id].items.get(name) {
+            Some(&item) => Ok(item),
+            None => Err(Diagnostic::wrong(
+                format!("module `{}` has no `pub` item `{}`", module, name),
+                &self.file,
+                pos.clone()
+            )),
+        }
+    }
+
+    // the item `name` of `record`, if that's a module imported whole and not a variable
+    fn module_item(&mut self, record: &Expression, name: &str, pos: &Pos) -> Result<Option<Item>, Diagnostic> {
+        let module = match record.node {
+            ExpressionNode::Identifier(ref module) if self.variable(module).is_none() => module,
+            _ => return Ok(None),
+        };
+
+        match self.scopes[0].modules.get(module) {
+            Some(&id) => self.item(id, module, name, pos).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // compiles a loop body that jumps back to `start`, binding `exit` right after it
+    fn compile_loop(&mut self, start: Label, exit: Label, body: &[Statement]) -> Result<(), Diagnostic> {
+        self.scope_mut().loops.push(Loop { start, exit });
+
+        self.compile_block(body)?;
+
+        self.scope_mut().loops.pop();
+
+        self.asm.jump(OpCode::Jmp, start);
+        self.asm.bind(exit);
+
+        Ok(())
+    }
+
+    fn compile_assignment(&mut self, target: &Expression, value: &Expression) -> Result<(), Diagnostic> {
+        use self::ExpressionNode::*;
+
+        match target.node {
+            Identifier(ref name) => {
+                self.compile_expression(value)?;
+
+                match self.variable(name) {
+                    Some(Variable::Upvalue(index)) => self.emit(OpCode::SetUpvalue(index)),
+                    Some(Variable::Global(slot)) => self.emit(OpCode::SetGlobal(slot)),
+
+                    _ => {
+                        let slot = self.local(name);
+
+                        self.emit(OpCode::SetLocal(slot))
+                    },
+                };
+            },
+
+            Index(ref indexed, ref index, true) => {
+                self.compile_expression(indexed)?;
+                self.compile_expression(index)?;
+                self.compile_expression(value)?;
+
+                self.emit(OpCode::SetElement);
+            },
+
+            Index(ref record, ref field, false) => {
+                let name = self.field_name(field)?;
+
+                match self.module_item(record, &name, &target.pos)? {
+                    Some(Item::Global(slot)) => {
+                        self.compile_expression(value)?;
+
+                        self.emit(OpCode::SetGlobal(slot));
+                    },
+
+                    Some(Item::Funk(_)) => {
+                        return Err(Diagnostic::wrong(
+                            "can't assign to a funk",
+                            &self.file,
+                            target.pos.clone())
+                        )
+                    },
+
+                    None => {
+                        self.compile_expression(record)?;
+                        self.compile_expression(value)?;
+
+                        self.asm.set_field(&name);
+                    },
+                }
+            },
+
+            _ => {
+                return Err(Diagnostic::wrong(
+                    "can't assign to this",
+                    &self.file,
+                    target.pos.clone())
+                )
+            },
+        }
+
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &Expression) -> Result<(), Diagnostic> {
+        self.enter(&expression.pos);
+
+        let result = self.compile_expression_node(expression);
+
+        self.leave();
+
+        result
+    }
+
+    fn compile_expression_node(&mut self, expression: &Expression) -> Result<(), Diagnostic> {
+        use self::ExpressionNode::*;
+
+        match expression.node {
+            Int(n) => {
+                let n = match i32::try_from(n) {
+                    Ok(n) => n,
+                    Err(_) => {
+                        return Err(Diagnostic::wrong(
+                            "int literal is too large",
+                            &self.file,
+                            expression.pos.clone())
+                        )
+                    },
+                };
+
+                self.emit(OpCode::LoadInt(n));
+            },
+
+            Float(n) => {
+                self.asm.load_float(n);
+            },
+
+            Str(ref content) => {
+                self.asm.load_string(content);
+            },
+
+            Char(c) => {
+                self.emit(OpCode::LoadChar(c));
+            },
+
+            Bool(b) => {
+                self.emit(OpCode::LoadBool(b));
+            },
+
+            Empty => {
+                self.emit(OpCode::LoadNil);
+            },
+
+            Neg(ref expression) => {
+                self.compile_expression(expression)?;
+                self.emit(OpCode::Neg);
+            },
+
+            Not(ref expression) => {
+                self.compile_expression(expression)?;
+                self.emit(OpCode::Not);
+            },
+
+            Identifier(ref name) => {
+                if let Some(variable) = self.variable(name) {
+                    match variable {
+                        Variable::Local(slot) => self.emit(OpCode::LoadLocal(slot)),
+                        Variable::Upvalue(index) => self.emit(OpCode::LoadUpvalue(index)),
+                        Variable::Global(slot) => self.emit(OpCode::LoadGlobal(slot)),
+                    };
+                } else if let Some(id) = self.function(name) {
+                    self.asm.jump(OpCode::LoadFunk, self.functions[id].label);
+                } else {
+                    return Err(Diagnostic::wrong(
+                        format!("can't find `{}` in this scope", name),
+                        &self.file,
+                        expression.pos.clone()
+                    ));
+                }
+            },
+
+            Binary(ref left, ref operator, ref right) => {
+                use self::Operator::*;
+
+                let op = match *operator {
+                    Add => OpCode::Add,
+                    Sub => OpCode::Sub,
+                    Mul => OpCode::Mul,
+                    Div => OpCode::Div,
+                    Mod => OpCode::Mod,
+                    Pow => OpCode::Pow,
+                    Concat => OpCode::Concat,
+                    Eq => OpCode::Eq,
+                    Lt => OpCode::Lt,
+                    Gt => OpCode::Gt,
+                    NEq => OpCode::NEq,
+                    LtEq => OpCode::LtEq,
+                    GtEq => OpCode::GtEq,
+
+                    And => return self.compile_short_circuit(left, false, right),
+                    Or => return self.compile_short_circuit(left, true, right),
+                };
+
+                self.compile_expression(left)?;
+                self.compile_expression(right)?;
+
+                self.emit(op);
+            },
+
+            Array(ref content) => {
+                for element in content {
+                    self.compile_expression(element)?
+                }
+
+                self.emit(OpCode::LoadArray(content.len() as u32));
+            },
+
+            Index(ref indexed, ref index, true) => {
+                self.compile_expression(indexed)?;
+                self.compile_expression(index)?;
+
+                self.emit(OpCode::LoadElement);
+            },
+
+            Record(ref ty, ref fields) => {
+                if let Some(ref ty) = *ty {
+                    if !self.asm.program.types.iter().any(|known| known.name == *ty) {
+                        return Err(Diagnostic::wrong(
+                            format!("can't find type `{}`", ty),
+                            &self.file,
+                            expression.pos.clone()
+                        ).with_note(format!("types are declared by `implement {}:`", ty)));
+                    }
+                }
+
+                let mut names: Vec<String> = Vec::new();
+
+                for (name, value) in fields {
+                    if names.contains(name) {
+                        return Err(Diagnostic::wrong(
+                            format!("field `{}` is defined more than once", name),
+                            &self.file,
+                            value.pos.clone()
+                        ));
+                    }
+
+                    self.compile_expression(value)?;
+
+                    names.push(name.clone())
+                }
+
+                self.asm.load_record(names);
+
+                if let Some(ref ty) = *ty {
+                    self.asm.instance(ty);
+                }
+            },
+
+            Index(ref record, ref field, false) => {
+                let name = self.field_name(field)?;
+
+                match self.module_item(record, &name, &expression.pos)? {
+                    Some(Item::Funk(id)) => {
+                        self.asm.jump(OpCode::LoadFunk, self.functions[id].label);
+                    },
+
+                    Some(Item::Global(slot)) => {
+                        self.emit(OpCode::LoadGlobal(slot));
+                    },
+
+                    None => {
+                        self.compile_expression(record)?;
+
+                        self.asm.load_field(&name);
+                    },
+                }
+            },
+
+            Range(ref start, ref end, inclusive) => {
+                self.compile_expression(start)?;
+                self.compile_expression(end)?;
+
+                self.emit(OpCode::LoadRange(inclusive));
+            },
+
+            Format(ref parts) => {
+                for part in parts {
+                    self.compile_expression(part)?
+                }
+
+                self.emit(OpCode::Format(parts.len() as u32));
+            },
+
+            Lambda(ref params, ref body) => {
+                self.compile_closure(params, |compiler| {
+                    compiler.compile_expression(body)?;
+                    compiler.emit(OpCode::Ret);
+
+                    Ok(())
+                })?
+            },
+
+            Call(ref callee, ref args) => {
+                // `a.b(c)` calls a method of `a`, unless `a` is a module
+                if let Index(ref receiver, ref field, false) = callee.node {
+                    let name = self.field_name(field)?;
+
+                    if self.module_item(receiver, &name, &callee.pos)?.is_none() {
+                        self.compile_expression(receiver)?;
+
+                        for arg in args {
+                            self.compile_expression(arg)?
+                        }
+
+                        self.asm.invoke(&name, args.len() as u32);
+
+                        return Ok(());
+                    }
+                }
+
+                // a variable shadows funks and natives of the same name
+                let named = match callee.node {
+                    Identifier(ref name) if self.variable(name).is_none() => Some(name),
+                    _ => None,
+                };
+
+                let function = named.and_then(|name| self.function(name));
+
+                let native = match (named, function) {
+                    (Some(name), None) => self.natives.get(name).cloned(),
+                    _ => None,
+                };
+
+                // calls by name are counted here, anything else is left to `Enter`
+                let arity = function.map(|id| self.functions[id].arity).or(native.map(|arity| arity as usize));
+
+                if let (Some(name), Some(arity)) = (named, arity) {
+                    if args.len() != arity {
+                        return Err(Diagnostic::wrong(
+                            format!("expected {} arguments, found {}", arity, args.len()),
+                            &self.file,
+                            expression.pos.clone()
+                        ).with_note(format!("`{}` takes {} arguments", name, arity)));
+                    }
+                }
+
+                if native.is_none() {
+                    self.compile_expression(callee)?
+                }
+
+                for arg in args {
+                    self.compile_expression(arg)?
+                }
+
+                match (named, native) {
+                    (Some(name), Some(arity)) => self.asm.call_native(name, arity),
+                    _ => self.emit(OpCode::Call(args.len() as u32)),
+                };
+            },
+
+            _ => {
+                return Err(Diagnostic::wrong(
+                    "this kind of expression isn't supported yet",
+                    &self.file,
+                    expression.pos.clone()
+                ))
+            },
+        }
+
+        Ok(())
+    }
+
+    // `and` settles to false and `or` to true as soon as one side is, skipping the right
+    // side when the left one already did
+    fn compile_short_circuit(&mut self, left: &Expression, settles_to: bool, right: &Expression) -> Result<(), Diagnostic> {
+        let jump: fn(u32) -> OpCode = if settles_to { OpCode::JmpIf } else { OpCode::JmpIfNot };
+
+        let settled = self.asm.label();
+        let end = self.asm.label();
+
+        self.compile_expression(left)?;
+        self.asm.jump(jump, settled);
+
+        self.compile_expression(right)?;
+        self.asm.jump(jump, settled);
+
+        self.emit(OpCode::LoadBool(!settles_to));
+        self.asm.jump(OpCode::Jmp, end);
+
+        self.asm.bind(settled);
+        self.emit(OpCode::LoadBool(settles_to));
+
+        self.asm.bind(end);
+
+        Ok(())
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        self.asm.emit(op)
+    }
+
+    fn enter(&mut self, pos: &Pos) {
+        self.positions.push(pos.clone());
+        self.asm.program.locate(pos)
+    }
+
+    // code emitted after a child node belongs to its parent again
+    fn leave(&mut self) {
+        self.positions.pop();
+
+        if let Some(pos) = self.positions.last() {
+            self.asm.program.locate(pos)
+        }
+    }
+
+    // `.field` is parsed as an index with the field as an identifier
+    fn field_name(&self, field: &Expression) -> Result<String, Diagnostic> {
+        match field.node {
+            ExpressionNode::Identifier(ref name) => Ok(name.clone()),
+            _ => Err(Diagnostic::wrong("expected a field name", &self.file, field.pos.clone())),
+        }
+    }
+
+    // finds the slot of a local, allocating the next free one for new names
+    fn local(&mut self, name: &str) -> u32 {
+        if let Some(slot) = self.scope().locals.get(name) {
+            return *slot;
+        }
+
+        let slot = if self.scopes.len() == 1 {
+            self.globals += 1;
+            self.asm.program.globals = self.globals;
+
+            self.globals - 1
+        } else {
+            self.scope().locals.len() as u32
+        };
+
+        self.scope_mut().locals.insert(name.to_string(), slot);
+
+        slot
+    }
+
+    fn variable(&mut self, name: &str) -> Option<Variable> {
+        self.resolve(self.scopes.len() - 1, name)
+    }
+
+    // looks for `name` in the funk at `depth` and then the ones around it, capturing it in
+    // every funk in between as it goes
+    fn resolve(&mut self, depth: usize, name: &str) -> Option<Variable> {
+        if let Some(&slot) = self.scopes[depth].locals.get(name) {
+            return Some(Variable::Local(slot));
+        }
+
+        if depth == 0 {
+            return None;
+        }
+
+        let capture = match self.resolve(depth - 1, name)? {
+            Variable::Local(slot) if depth == 1 => return Some(Variable::Global(slot)),
+            Variable::Global(slot) => return Some(Variable::Global(slot)),
+
+            Variable::Local(slot) => Capture::Local(slot),
+            Variable::Upvalue(index) => Capture::Upvalue(index),
+        };
+
+        let upvalues = &mut self.scopes[depth].upvalues;
+
+        let index = match upvalues.iter().position(|&upvalue| upvalue == capture) {
+            Some(index) => index,
+            None => {
+                upvalues.push(capture);
+                upvalues.len() - 1
+            },
+        };
+
+        Some(Variable::Upvalue(index as u32))
+    }
+
+    fn function(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|scope| scope.functions.get(name))
+            .next()
+            .cloned()
+    }
+
+    fn scope(&self) -> &Scope {
+        self.scopes.last().unwrap()
+    }
+
+    fn scope_mut(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niels::engine::Engine;
+
+    // an engine that finds `modules`, written out to a directory of their own
+    fn engine_with(test: &str, modules: &[(&str, &str)]) -> Engine {
+        let directory = std::env::temp_dir().join(format!("niels-{}-{}", test, std::process::id()));
+
+        for (name, code) in modules {
+            let file = directory.join(name);
+
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, code).unwrap();
+        }
+
+        let mut engine = Engine::new();
+
+        engine.add_search_path(directory);
+        engine
+    }
+
+    fn wrong(engine: &mut Engine, code: &str) -> String {
+        match engine.eval::<()>(code).unwrap_err()[0].response {
+            Response::Wrong(ref message) => message.clone(),
+            ref other => panic!("expected an error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn imports_pub_items() {
+        let mut engine = engine_with("pub", &[
+            ("geo/area.niels", "pub funk square(a): return a * a\npub side = 3\nfunk hidden(): return 0\n"),
+        ]);
+
+        assert_eq!(engine.eval::<i32>("import geo.area: square, side\nsquare(side)").unwrap(), 9);
+        assert_eq!(engine.eval::<i32>("import geo.area\narea.square(4)").unwrap(), 16);
+    }
+
+    #[test]
+    fn modules_are_compiled_once() {
+        let mut engine = engine_with("once", &[
+            ("count.niels", "pub n = 0\npub funk bump():\n    n = n + 1\n    return n\n"),
+            ("a.niels", "import count: bump\npub funk a(): return bump()\n"),
+        ]);
+
+        // `a` and the top level bump the same `n`
+        assert_eq!(engine.eval::<i32>("import a: a\nimport count: bump\na()\nbump()").unwrap(), 2);
+    }
+
+    #[test]
+    fn private_items_are_not_imported() {
+        let mut engine = engine_with("private", &[
+            ("lib.niels", "pub funk open(): return 1\nfunk closed(): return 2\n"),
+        ]);
+
+        assert_eq!(wrong(&mut engine, "import lib: closed"), "module `lib` has no `pub` item `closed`");
+        assert_eq!(wrong(&mut engine, "import lib\nlib.closed()"), "module `lib` has no `pub` item `closed`");
+    }
+
+    #[test]
+    fn missing_modules_are_diagnosed() {
+        let mut engine = engine_with("missing", &[]);
+
+        assert_eq!(wrong(&mut engine, "import nowhere: x"), "can't find module `nowhere`");
+    }
+
+    #[test]
+    fn import_cycles_are_diagnosed() {
+        let mut engine = engine_with("cycle", &[
+            ("ping.niels", "import pong: g\npub funk f(): return 1\n"),
+            ("pong.niels", "import ping: f\npub funk g(): return 2\n"),
+        ]);
+
+        let message = wrong(&mut engine, "import ping: f");
+
+        assert!(message.starts_with("import cycle: "), "{}", message);
+        assert!(message.contains("ping.niels -> ") && message.contains("pong.niels -> "), "{}", message);
+    }
+
+    #[test]
+    fn loops_break_and_skip() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<i32>("s = 0\nfor i in 0..10:\n    if i == 7:\n        break\n    if i % 2 == 0:\n        skip\n    s = s + i\ns").unwrap(), 9);
+        assert_eq!(engine.eval::<i32>("n = 0\nw = 0\nwhile true:\n    n = n + 1\n    if n > 5:\n        break\n    if n == 2:\n        skip\n    w = w + n\nw").unwrap(), 13);
+    }
+
+    #[test]
+    fn break_leaves_only_the_innermost_loop() {
+        let mut engine = Engine::new();
+
+        let t: i32 = engine.eval("t = 0\nfor c in [1, 2, 3]:\n    for d in [10, 20]:\n        if d == 20:\n            break\n        t = t + c * d\nt").unwrap();
+
+        assert_eq!(t, 60);
+    }
+
+    #[test]
+    fn for_goes_over_arrays_strings_and_ranges() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<Vec<char>>("out = []\nfor c in \"h\u{e9}y\":\n    out = out ++ [c]\nout").unwrap(), vec!('h', '\u{e9}', 'y'));
+        assert_eq!(engine.eval::<Vec<i32>>("out = []\nfor i in 1...3:\n    out = out ++ [i]\nout").unwrap(), vec!(1, 2, 3));
+        assert_eq!(engine.eval::<Vec<i32>>("out = []\nfor i in 3..1:\n    out = out ++ [i]\nout").unwrap(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn break_and_skip_need_a_loop() {
+        let mut engine = Engine::new();
+
+        assert_eq!(wrong(&mut engine, "break"), "can't break outside of a loop");
+        assert_eq!(wrong(&mut engine, "funk f():\n    skip"), "can't skip outside of a loop");
+
+        // a funk inside a loop doesn't get to leave it
+        assert_eq!(wrong(&mut engine, "for i in 0..3:\n    funk f():\n        break"), "can't break outside of a loop");
+    }
+
+    #[test]
+    fn if_takes_the_first_true_branch() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("funk grade(n):\n    if n > 90:\n        return \"a\"\n    elif n > 50:\n        return \"b\"\n    else:\n        return \"c\"").unwrap();
+
+        assert_eq!(engine.eval::<String>("grade(95) ++ grade(60) ++ grade(1)").unwrap(), "abc");
+        assert_eq!(engine.eval::<i32>("x = 0\nif false:\n    x = 1\nelif false:\n    x = 2\nx").unwrap(), 0);
+        assert_eq!(engine.eval::<i32>("y = 0\nif true:\n    y = 1\nelif true:\n    y = 2\ny").unwrap(), 1);
+    }
+}
@@ -0,0 +1,207 @@
+use super::*;
+
+// rust values a script can be handed, allocated on the VM's heap where they need to be
+pub trait IntoValue {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value;
+}
+
+// rust values a script's values can be read back as, if they're of the right kind
+pub trait FromValue: Sized {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Option<Self>;
+}
+
+// the arguments of a call, like `(1, "two")`
+pub trait IntoArgs {
+    fn into_args(self, vm: &mut VirtualMachine) -> Vec<Value>;
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::nil()
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::bool(self)
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::int(self)
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::float(self)
+    }
+}
+
+impl IntoValue for char {
+    fn into_value(self, _: &mut VirtualMachine) -> Value {
+        Value::char(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        vm.alloc(HeapValue::Str(self.to_string()))
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        vm.alloc(HeapValue::Str(self))
+    }
+}
+
+impl IntoValue for HeapValue {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        vm.alloc(self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        let content = self.into_iter().map(|element| element.into_value(vm)).collect();
+
+        vm.alloc(HeapValue::Array(content))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, vm: &mut VirtualMachine) -> Value {
+        match self {
+            Some(a) => a.into_value(vm),
+            None => Value::nil(),
+        }
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value, _: &VirtualMachine) -> Option<Self> {
+        Some(value)
+    }
+}
+
+// for when the value doesn't matter
+impl FromValue for () {
+    fn from_value(_: Value, _: &VirtualMachine) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value, _: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Bool(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: Value, _: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Int(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// ints widen, like they do in arithmetic
+impl FromValue for f64 {
+    fn from_value(value: Value, _: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Float(a) => Some(a),
+            Variant::Int(a) => Some(a as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: Value, _: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Char(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Option<Self> {
+        match HeapValue::from_value(value, vm)? {
+            HeapValue::Str(content) => Some(content),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for HeapValue {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Pointer(pointer) => vm.heap.get(pointer).cloned(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Option<Self> {
+        let content = match value.unpack() {
+            Variant::Pointer(pointer) => match vm.heap.get(pointer)? {
+                HeapValue::Array(content) => content,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        content.iter().map(|&element| T::from_value(element, vm)).collect()
+    }
+}
+
+// `nil` is `None`, anything else has to convert
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value, vm: &VirtualMachine) -> Option<Self> {
+        match value.unpack() {
+            Variant::Nil => Some(None),
+            _ => T::from_value(value, vm).map(Some),
+        }
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self, _: &mut VirtualMachine) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! tuple_args {
+    ($($name:ident)*) => {
+        impl<$($name: IntoValue),*> IntoArgs for ($($name,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn into_args(self, vm: &mut VirtualMachine) -> Vec<Value> {
+                let ($($name,)*) = self;
+
+                vec!($($name.into_value(vm)),*)
+            }
+        }
+    };
+}
+
+tuple_args!();
+tuple_args!(A);
+tuple_args!(A B);
+tuple_args!(A B C);
+tuple_args!(A B C D);
+tuple_args!(A B C D E);
+tuple_args!(A B C D E F);
@@ -0,0 +1,220 @@
+use super::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// a listing of `program`, one instruction per line with its offset; jump targets get labels,
+// exported functions and methods their names, and each run of code is headed by the source line it
+// came from:
+//
+//     add:
+//         0005  Enter        2
+//              2 │     return a + b
+//         000a  LoadLocal    0
+//         000f  LoadLocal    1
+//         0014  Add
+//         0015  Ret
+pub fn disassemble(program: &Program) -> String {
+    let labels = labels(program);
+
+    let mut out = String::new();
+
+    if !program.constants.is_empty() {
+        out.push_str("constants:\n");
+
+        for (index, constant) in program.constants.iter().enumerate() {
+            writeln!(out, "    #{:<4} {}", index, describe(constant)).unwrap()
+        }
+
+        out.push('\n')
+    }
+
+    if !program.exports.is_empty() {
+        out.push_str("exports:\n");
+
+        for export in &program.exports {
+            writeln!(out, "    {}/{} at {:04x}", export.name, export.arity, export.address).unwrap()
+        }
+
+        out.push('\n')
+    }
+
+    if !program.types.is_empty() {
+        out.push_str("types:\n");
+
+        for ty in &program.types {
+            if ty.traits.is_empty() {
+                writeln!(out, "    {}", ty.name).unwrap()
+            } else {
+                writeln!(out, "    {} ({})", ty.name, ty.traits.join(", ")).unwrap()
+            }
+
+            for method in &ty.methods {
+                writeln!(out, "        {}/{} at {:04x}", method.name, method.arity, method.address).unwrap()
+            }
+        }
+
+        out.push('\n')
+    }
+
+    out.push_str("code:\n");
+
+    let mut offset = 0;
+    let mut line = None;
+    // a program made of several files heads the code of each with its name
+    let mut file = if program.files.len() > 1 { None } else { program.file(0) };
+
+    while offset < program.code.len() {
+        if let Some(label) = labels.get(&offset) {
+            writeln!(out, "{}:", label).unwrap()
+        }
+
+        if program.file(offset) != file {
+            file = program.file(offset);
+            line = None;
+
+            writeln!(out, "{:>10} {}", "-->", file.unwrap_or("?")).unwrap()
+        }
+
+        if let Some(Pos((number, ref content), _)) = program.position(offset) {
+            if line != Some(*number) {
+                writeln!(out, "{:>10} │ {}", number, content.trim_end()).unwrap();
+
+                line = Some(*number)
+            }
+        }
+
+        match program.decode(offset) {
+            Some((op, len)) => {
+                writeln!(out, "    {:04x}  {}", offset, instruction(program, &labels, &op)).unwrap();
+
+                offset += len
+            },
+
+            None => {
+                writeln!(out, "    {:04x}  <bad instruction {:#04x}>", offset, program.code[offset]).unwrap();
+
+                offset += 1
+            },
+        }
+    }
+
+    out
+}
+
+// names for every address something jumps to, exports keeping their own
+fn labels(program: &Program) -> BTreeMap<usize, String> {
+    let mut starts = Vec::new();
+    let mut targets = Vec::new();
+    let mut offset = 0;
+
+    while let Some((op, len)) = program.decode(offset) {
+        starts.push(offset);
+        targets.extend(op.target().map(|a| a as usize));
+
+        offset += len
+    }
+
+    let mut labels = BTreeMap::new();
+
+    for export in &program.exports {
+        labels.insert(export.address as usize, export.name.clone());
+    }
+
+    for ty in &program.types {
+        for method in &ty.methods {
+            labels.insert(method.address as usize, format!("{}.{}", ty.name, method.name));
+        }
+    }
+
+    targets.sort_unstable();
+    targets.dedup();
+
+    let mut count = 0;
+
+    // an address in the middle of an instruction or past the end is left as a number
+    for target in targets {
+        if starts.binary_search(&target).is_ok() && !labels.contains_key(&target) {
+            labels.insert(target, format!("L{}", count));
+
+            count += 1
+        }
+    }
+
+    labels
+}
+
+fn instruction(program: &Program, labels: &BTreeMap<usize, String>, op: &OpCode) -> String {
+    let name = op.name();
+
+    let operand = match op.operand() {
+        Some(operand) => operand,
+        None => return name.to_string(),
+    };
+
+    if let Some(target) = op.target() {
+        return match labels.get(&(target as usize)) {
+            Some(label) => format!("{:<12} {}", name, label),
+            None => format!("{:<12} {:04x} ; not an instruction", name, target),
+        };
+    }
+
+    if let Some(index) = op.constant() {
+        let constant = match program.constant(index) {
+            Some(constant) => describe(constant),
+            None => String::from("<missing constant>"),
+        };
+
+        return format!("{:<12} #{:<4} ; {}", name, index, constant);
+    }
+
+    format!("{:<12} {}", name, operand)
+}
+
+fn describe(constant: &Constant) -> String {
+    match *constant {
+        Constant::Float(a) => format!("{:?}", a),
+        Constant::Str(ref content) => format!("{:?}", content),
+        Constant::Names(ref names) => format!("{{ {} }}", names.join(", ")),
+        Constant::Native(ref name, arity) => format!("native {}/{}", name, arity),
+        Constant::Method(ref name, argc) => format!("method {}/{}", name, argc),
+        Constant::Captures(ref captures) => {
+            let captures = captures
+                .iter()
+                .map(|capture| match *capture {
+                    Capture::Local(slot) => format!("local {}", slot),
+                    Capture::Upvalue(index) => format!("upvalue {}", index),
+                })
+                .collect::<Vec<String>>();
+
+            format!("captures [{}]", captures.join(", "))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions_are_listed_by_name_and_operand() {
+        let mut asm = Assembler::new();
+        let end = asm.label();
+
+        asm.emit(OpCode::LoadChar('a'));
+        asm.emit(OpCode::LoadInt(-1));
+        asm.emit(OpCode::LoadNil);
+        asm.load_string("hi");
+        asm.jump(OpCode::Jmp, end);
+        asm.bind(end);
+        asm.emit(OpCode::Pop);
+
+        let listing = disassemble(&asm.program);
+
+        assert!(listing.contains("LoadChar     'a'"), "{}", listing);
+        assert!(listing.contains("LoadInt      -1"), "{}", listing);
+        assert!(listing.contains("LoadNil\n"), "{}", listing);
+        assert!(listing.contains("LoadString   #0    ; \"hi\""), "{}", listing);
+        assert!(listing.contains("Jmp          L0"), "{}", listing);
+    }
+}
@@ -0,0 +1,249 @@
+use super::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeapValue {
+    Str(String),
+    Array(Vec<Value>),
+    Range(i32, i32, bool), // start, end, inclusive; elements are produced on demand
+    Record(Option<String>, Vec<(String, Value)>), // the type, if it was given one
+    Closure(u32, Vec<u32>), // the funk's address, and pointers to the upvalues it captured
+    Upvalue(Upvalue),
+}
+
+// a local captured by a closure, shared by every closure that captured it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upvalue {
+    Open(usize),   // still in the slot of a call that hasn't returned
+    Closed(Value), // moved out of it once the call did
+}
+
+impl HeapValue {
+    // end of a range as an exclusive bound, widened so `...` up to `i32::MAX` can't overflow
+    pub fn range_end(end: i32, inclusive: bool) -> i64 {
+        end as i64 + inclusive as i64
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GcConfig {
+    pub threshold: usize, // live objects before the first collection
+    pub growth: f64,      // the next collection waits for this many times the survivors
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            threshold: 1024,
+            growth: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated: usize,
+    pub freed: usize,
+    pub live: usize,
+    pub peak: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Slot {
+    value: Option<HeapValue>,
+    marked: bool,
+}
+
+// objects addressed by `Value::Pointer`; freed slots are reused before the heap grows
+#[derive(Clone, Debug)]
+pub struct Heap {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+
+    config: GcConfig, // see `set_config`
+    pub stats: GcStats,
+
+    next_collection: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            slots: Vec::new(),
+            free: Vec::new(),
+
+            config,
+            stats: GcStats::default(),
+
+            next_collection: config.threshold,
+        }
+    }
+
+    pub fn config(&self) -> GcConfig {
+        self.config
+    }
+
+    // takes effect right away: the next collection is rescheduled as if `config` had been
+    // there all along
+    pub fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+
+        self.schedule()
+    }
+
+    pub fn alloc(&mut self, value: HeapValue) -> u32 {
+        let slot = Slot {
+            value: Some(value),
+            marked: false,
+        };
+
+        let pointer = match self.free.pop() {
+            Some(pointer) => {
+                self.slots[pointer as usize] = slot;
+                pointer
+            },
+
+            None => {
+                self.slots.push(slot);
+                (self.slots.len() - 1) as u32
+            },
+        };
+
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.live);
+
+        pointer
+    }
+
+    pub fn get(&self, pointer: u32) -> Option<&HeapValue> {
+        self.slots.get(pointer as usize).and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, pointer: u32) -> Option<&mut HeapValue> {
+        self.slots.get_mut(pointer as usize).and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stats.live >= self.next_collection
+    }
+
+    // mark-and-sweep: everything not reachable from `roots` is freed
+    pub fn collect<'v, I: Iterator<Item = &'v Value>>(&mut self, roots: I) {
+        let mut pending = Vec::new();
+
+        push_pointers(&mut pending, roots);
+
+        while let Some(pointer) = pending.pop() {
+            let slot = match self.slots.get_mut(pointer as usize) {
+                Some(slot) if !slot.marked && slot.value.is_some() => slot,
+                _ => continue,
+            };
+
+            slot.marked = true;
+
+            match *slot.value.as_ref().unwrap() {
+                HeapValue::Array(ref content) => push_pointers(&mut pending, content.iter()),
+                HeapValue::Record(_, ref fields) => push_pointers(&mut pending, fields.iter().map(|(_, value)| value)),
+                HeapValue::Closure(_, ref upvalues) => pending.extend(upvalues),
+                HeapValue::Upvalue(Upvalue::Closed(ref value)) => push_pointers(&mut pending, Some(value).into_iter()),
+                HeapValue::Str(_) | HeapValue::Range(..) | HeapValue::Upvalue(Upvalue::Open(_)) => (),
+            }
+        }
+
+        let mut freed = 0;
+
+        for (pointer, slot) in self.slots.iter_mut().enumerate() {
+            if slot.marked {
+                slot.marked = false
+            } else if slot.value.take().is_some() {
+                self.free.push(pointer as u32);
+                freed += 1
+            }
+        }
+
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live -= freed;
+
+        self.schedule()
+    }
+
+    // the first collection waits for the threshold, later ones for the survivors to grow
+    fn schedule(&mut self) {
+        let grown = (self.stats.live as f64 * self.config.growth) as usize;
+
+        self.next_collection = if self.stats.collections == 0 {
+            self.config.threshold
+        } else {
+            grown.max(self.config.threshold)
+        }
+    }
+}
+
+fn push_pointers<'v, I: Iterator<Item = &'v Value>>(pending: &mut Vec<u32>, values: I) {
+    for value in values {
+        if let Variant::Pointer(pointer) = value.unpack() {
+            pending.push(pointer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niels::engine::Engine;
+
+    fn config(threshold: usize) -> GcConfig {
+        GcConfig {
+            threshold,
+            ..GcConfig::default()
+        }
+    }
+
+    #[test]
+    fn a_new_config_applies_before_the_first_collection() {
+        let mut heap = Heap::new(GcConfig::default());
+
+        heap.set_config(config(2));
+
+        heap.alloc(HeapValue::Str(String::from("a")));
+        assert!(!heap.should_collect());
+
+        heap.alloc(HeapValue::Str(String::from("b")));
+        assert!(heap.should_collect());
+    }
+
+    #[test]
+    fn collections_free_only_unreachable_objects() {
+        let mut heap = Heap::new(config(1));
+
+        let kept = heap.alloc(HeapValue::Str(String::from("kept")));
+        let inner = heap.alloc(HeapValue::Str(String::from("inner")));
+        let array = heap.alloc(HeapValue::Array(vec!(Value::pointer(inner))));
+        let dropped = heap.alloc(HeapValue::Str(String::from("dropped")));
+
+        heap.collect([Value::pointer(kept), Value::pointer(array)].iter());
+
+        assert!(heap.get(kept).is_some());
+        assert!(heap.get(inner).is_some());
+        assert!(heap.get(dropped).is_none());
+
+        assert_eq!(heap.stats.freed, 1);
+        assert_eq!(heap.stats.live, 3);
+    }
+
+    #[test]
+    fn engines_can_be_tuned_by_their_host() {
+        let mut engine = Engine::new();
+
+        engine.set_gc_config(config(8));
+
+        engine.eval::<()>("for i in 0..100:\n    s = \"{i}\"").unwrap();
+
+        let stats = engine.gc_stats();
+
+        assert!(stats.collections > 0);
+        assert!(stats.peak < 100);
+    }
+}
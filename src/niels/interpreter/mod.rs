@@ -1,9 +1,27 @@
 pub mod vm;
+pub mod value;
+pub mod heap;
 pub mod opcode;
+pub mod program;
+pub mod assembler;
+pub mod bytecode;
+pub mod disassembler;
+pub mod verifier;
+pub mod compiler;
+pub mod convert;
 
 use super::error::*;
+use super::lexer::Pos;
 use super::parser::*;
 use super::source::*;
 
 pub use self::vm::*;
-pub use self::opcode::*;
\ No newline at end of file
+pub use self::value::*;
+pub use self::heap::*;
+pub use self::opcode::*;
+pub use self::program::*;
+pub use self::assembler::*;
+pub use self::disassembler::*;
+pub use self::verifier::*;
+pub use self::compiler::*;
+pub use self::convert::*;
@@ -1,39 +1,195 @@
-#[derive(Clone, PartialEq, Debug)]
-pub enum OpCode {
-    LoadInt(i32),
-    LoadFloat(f64),
-    LoadChar(char),
-    LoadString(String),
-    LoadBool(bool),
-    LoadLocal(u32),
-    LoadArray(u32),
-    LoadIndex(u32),
-
-    Deref,
-
-    PushFrame,
-    PopFrame,
-
-    Call(u32),
-    Ret,
-
-    SetLocal(u32),
-    SetIndex(u32),
-
-    Jmp(u32),
-    JmpIf(u32),
-
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Mod,
-    Or,
-    And,
-    Lt,
-    Gt,
-    Eq,
-    NEq,
-    LtEq,
-    GtEq,
-}
\ No newline at end of file
+// operands are packed little-endian right after their opcode byte
+pub trait Operand: Sized {
+    fn write(self, out: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Option<(Self, usize)>;
+}
+
+impl Operand for u32 {
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes())
+    }
+
+    fn read(bytes: &[u8]) -> Option<(Self, usize)> {
+        let bytes = bytes.get(.. 4)?;
+
+        Some((u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]), 4))
+    }
+}
+
+impl Operand for i32 {
+    fn write(self, out: &mut Vec<u8>) {
+        (self as u32).write(out)
+    }
+
+    fn read(bytes: &[u8]) -> Option<(Self, usize)> {
+        u32::read(bytes).map(|(a, len)| (a as i32, len))
+    }
+}
+
+impl Operand for char {
+    fn write(self, out: &mut Vec<u8>) {
+        (self as u32).write(out)
+    }
+
+    fn read(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (a, len) = u32::read(bytes)?;
+
+        Some((std::char::from_u32(a)?, len))
+    }
+}
+
+impl Operand for bool {
+    fn write(self, out: &mut Vec<u8>) {
+        out.push(self as u8)
+    }
+
+    fn read(bytes: &[u8]) -> Option<(Self, usize)> {
+        match *bytes.first()? {
+            0 => Some((false, 1)),
+            1 => Some((true, 1)),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! opcodes {
+    ($($byte:literal $name:ident $(($operand:ty))?,)+) => {
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        pub enum OpCode {
+            $($name $(($operand))?,)+
+        }
+
+        impl OpCode {
+            pub fn encode(&self, out: &mut Vec<u8>) {
+                match *self {
+                    $(opcodes!(@pattern $name a $($operand)?) => {
+                        out.push($byte);
+                        $(<$operand as Operand>::write(a, out);)?
+                    },)+
+                }
+            }
+
+            // the instruction at the start of `bytes` and its encoded length
+            pub fn decode(bytes: &[u8]) -> Option<(OpCode, usize)> {
+                let (&byte, operands) = bytes.split_first()?;
+
+                match byte {
+                    $($byte => Some(opcodes!(@decode operands $name $($operand)?)),)+
+                    _ => None,
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(opcodes!(@pattern $name _a $($operand)?) => stringify!($name),)+
+                }
+            }
+
+            // the operand as listings show it, for the instructions that have one
+            pub fn operand(&self) -> Option<String> {
+                match *self {
+                    $(opcodes!(@pattern $name a $($operand)?) => opcodes!(@operand a $($operand)?),)+
+                }
+            }
+        }
+    };
+
+    (@pattern $name:ident $a:ident $operand:ty) => { OpCode::$name($a) };
+    (@pattern $name:ident $a:ident) => { OpCode::$name };
+
+    (@decode $operands:ident $name:ident $operand:ty) => {{
+        let (a, len) = <$operand as Operand>::read($operands)?;
+
+        (OpCode::$name(a), 1 + len)
+    }};
+    (@decode $operands:ident $name:ident) => { (OpCode::$name, 1) };
+
+    (@operand $a:ident $operand:ty) => { Some(format!("{:?}", $a)) };
+    (@operand $a:ident) => { None };
+}
+
+// `constant` marks operands that index the program's constant pool
+opcodes! {
+    0x00 LoadInt(i32),
+    0x01 LoadFloat(u32),  // constant
+    0x02 LoadChar(char),
+    0x03 LoadString(u32), // constant
+    0x04 LoadBool(bool),
+    0x05 LoadNil,
+    0x06 LoadLocal(u32),
+    0x07 LoadArray(u32),
+    0x08 LoadRange(bool),
+    0x09 LoadRecord(u32), // constant
+    0x0a LoadField(u32),  // constant
+    0x0b LoadIndex(u32),
+    0x0c LoadElement,
+    0x0d LoadFunk(u32),
+    0x0e LoadUpvalue(u32),
+    0x0f LoadGlobal(u32),
+
+    0x11 Pop,
+
+    0x12 Enter(u32),      // the funk's arity, checked against the call's
+    0x13 Call(u32),       // the number of arguments, pushed after the funk
+    0x14 Ret,
+    0x15 CallNative(u32), // constant
+    0x16 Closure(u32),    // constant, the captures of the funk on top
+    0x17 Invoke(u32),     // constant, the method called on a receiver pushed before the arguments
+    0x18 Instance(u32),   // constant, the type given to the record on top
+    0x19 Format(u32),     // the number of values displayed and joined into a string
+
+    0x20 SetLocal(u32),
+    0x21 SetIndex(u32),
+    0x22 SetElement,
+    0x23 SetField(u32),   // constant
+    0x24 SetUpvalue(u32),
+    0x25 SetGlobal(u32),
+
+    0x30 Jmp(u32),
+    0x31 JmpIf(u32),
+    0x32 JmpIfNot(u32),
+    0x33 Next(u32),
+
+    0x40 Neg,
+    0x41 Not,
+
+    0x50 Add,
+    0x51 Sub,
+    0x52 Mul,
+    0x53 Div,
+    0x54 Mod,
+    0x55 Pow,
+    0x56 Concat,
+    0x57 Lt,
+    0x58 Gt,
+    0x59 Eq,
+    0x5a NEq,
+    0x5b LtEq,
+    0x5c GtEq,
+}
+
+impl OpCode {
+    // the address a jump goes to, or a funk starts at
+    pub fn target(&self) -> Option<u32> {
+        match *self {
+            OpCode::Jmp(a) | OpCode::JmpIf(a) | OpCode::JmpIfNot(a) | OpCode::Next(a) | OpCode::LoadFunk(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    // the constant pool entry an instruction refers to
+    pub fn constant(&self) -> Option<u32> {
+        match *self {
+            OpCode::LoadFloat(a)
+            | OpCode::LoadString(a)
+            | OpCode::LoadRecord(a)
+            | OpCode::LoadField(a)
+            | OpCode::SetField(a)
+            | OpCode::CallNative(a)
+            | OpCode::Closure(a)
+            | OpCode::Invoke(a)
+            | OpCode::Instance(a) => Some(a),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use super::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    Float(f64),
+    Str(String),
+    Names(Vec<String>),  // the fields of a record, in order
+    Native(String, u32), // the name and arity of a funk provided by the host
+    Captures(Vec<Capture>),
+    Method(String, u32), // the name of a method called, and the arguments it's given
+}
+
+// what a closure captures: a local of the funk creating it, or one of that funk's own upvalues
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capture {
+    Local(u32),
+    Upvalue(u32),
+}
+
+// a top-level `pub funk`, callable from outside the program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub address: u32,
+    pub arity: u32,
+}
+
+// a record type, with the funks `implement` blocks attached to it; records only carry the
+// name of theirs, so types are shared by every module
+#[derive(Clone, Debug, PartialEq)]
+pub struct Type {
+    pub name: String,
+    pub traits: Vec<String>,
+    pub methods: Vec<Method>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub address: u32,
+    pub arity: u32, // counting `self`
+}
+
+// compiled bytecode, along with where in the source each instruction came from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub code: Vec<u8>, // packed instructions, see `OpCode::encode`
+    pub constants: Vec<Constant>,
+
+    // (first byte, position) pairs in code order; an entry covers every instruction up to
+    // the next one
+    pub positions: Vec<(usize, Pos)>,
+
+    // (first byte, file) pairs like the positions, as a program can be made of several modules
+    pub files: Vec<(usize, String)>,
+
+    pub exports: Vec<Export>,
+
+    pub types: Vec<Type>,
+
+    // the slots top-level locals take, which calls have to keep clear of even before the
+    // locals are first assigned
+    pub globals: u32,
+}
+
+impl Program {
+    pub fn push(&mut self, op: OpCode) -> usize {
+        let offset = self.code.len();
+
+        op.encode(&mut self.code);
+
+        offset
+    }
+
+    // overwrites the instruction at `offset` with one of the same size, like a resolved jump
+    pub fn patch(&mut self, offset: usize, op: OpCode) {
+        let mut bytes = Vec::new();
+
+        op.encode(&mut bytes);
+
+        let old = self.decode(offset).map(|(_, len)| len);
+
+        assert_eq!(old, Some(bytes.len()), "patching `{:?}` over a different instruction", op);
+
+        self.code[offset .. offset + bytes.len()].copy_from_slice(&bytes)
+    }
+
+    pub fn decode(&self, offset: usize) -> Option<(OpCode, usize)> {
+        OpCode::decode(self.code.get(offset ..)?)
+    }
+
+    // the instructions that follow come from `pos`
+    pub fn locate(&mut self, pos: &Pos) {
+        let offset = self.code.len();
+
+        // an entry no instruction ended up using is replaced
+        if let Some(&(start, _)) = self.positions.last() {
+            if start == offset {
+                self.positions.pop();
+            }
+        }
+
+        if self.positions.last().map(|(_, last)| last != pos).unwrap_or(true) {
+            self.positions.push((offset, pos.clone()))
+        }
+    }
+
+    // the instructions that follow come from `file`
+    pub fn locate_file(&mut self, file: &str) {
+        let offset = self.code.len();
+
+        if let Some(&(start, _)) = self.files.last() {
+            if start == offset {
+                self.files.pop();
+            }
+        }
+
+        if self.files.last().map(|(_, last)| last != file).unwrap_or(true) {
+            self.files.push((offset, file.to_string()))
+        }
+    }
+
+    // index of `constant` in the pool, adding it if it isn't there yet
+    pub fn intern(&mut self, constant: Constant) -> u32 {
+        match self.constants.iter().position(|c| *c == constant) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(constant);
+                (self.constants.len() - 1) as u32
+            },
+        }
+    }
+
+    pub fn constant(&self, index: u32) -> Option<&Constant> {
+        self.constants.get(index as usize)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.positions.retain(|&(start, _)| start < len);
+        self.files.retain(|&(start, _)| start < len);
+        self.exports.retain(|export| (export.address as usize) < len);
+
+        for ty in &mut self.types {
+            ty.methods.retain(|method| (method.address as usize) < len)
+        }
+    }
+
+    // the index of the type called `name`, adding it if there's none yet
+    pub fn declare_type(&mut self, name: &str) -> usize {
+        match self.types.iter().position(|ty| ty.name == name) {
+            Some(index) => index,
+            None => {
+                self.types.push(Type {
+                    name: name.to_string(),
+                    traits: Vec::new(),
+                    methods: Vec::new(),
+                });
+
+                self.types.len() - 1
+            },
+        }
+    }
+
+    // a method of the type called `name`, if it has one
+    pub fn method(&self, name: &str, method: &str) -> Option<&Method> {
+        let ty = self.types.iter().find(|ty| ty.name == name)?;
+
+        ty.methods.iter().find(|m| m.name == method)
+    }
+
+    pub fn position(&self, ip: usize) -> Option<&Pos> {
+        self.entry(&self.positions, ip)
+    }
+
+    pub fn file(&self, ip: usize) -> Option<&str> {
+        self.entry(&self.files, ip).map(String::as_str)
+    }
+
+    // the entry of a table covering `ip`
+    fn entry<'a, T>(&self, table: &'a [(usize, T)], ip: usize) -> Option<&'a T> {
+        if ip >= self.code.len() {
+            return None;
+        }
+
+        let entry = match table.binary_search_by_key(&ip, |&(start, _)| start) {
+            Ok(entry) => entry,
+            Err(entry) => entry.checked_sub(1)?,
+        };
+
+        Some(&table[entry].1)
+    }
+}
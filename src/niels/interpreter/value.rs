@@ -0,0 +1,199 @@
+use nanbox::NanBox;
+
+use std::fmt;
+
+// tags stored in the unused bits of a quiet NaN; floats are left untagged
+const INT: u8 = 1;
+const CHAR: u8 = 2;
+const POINTER: u8 = 3;
+const BOOL: u8 = 4;
+const NIL: u8 = 5;
+const FUNK: u8 = 6;
+
+// the one NaN a float is allowed to carry, as every other NaN could be mistaken for a tag
+const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+// a value unpacked for matching on
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variant {
+    Float(f64),
+    Bool(bool),
+    Int(i32),
+    Char(char),
+    Pointer(u32),
+    Funk(u32), // the address of its `Enter`
+    Nil,
+}
+
+// a value packed into a single 64-bit word, half the size of the enum it replaced; that makes
+// moving values around cheaper, but arithmetic pays for unpacking (see benches/value.rs)
+#[derive(Clone, Copy)]
+pub struct Value(NanBox);
+
+impl Value {
+    pub fn float(a: f64) -> Self {
+        let a = if a.is_nan() { f64::from_bits(CANONICAL_NAN) } else { a };
+
+        unsafe { Value(NanBox::new(0, a)) }
+    }
+
+    // ints are boxed as their bits, since a sign-extended `i32` would spill into the tag
+    pub fn int(a: i32) -> Self {
+        unsafe { Value(NanBox::new(INT, a as u32)) }
+    }
+
+    pub fn char(a: char) -> Self {
+        unsafe { Value(NanBox::new(CHAR, a)) }
+    }
+
+    pub fn pointer(a: u32) -> Self {
+        unsafe { Value(NanBox::new(POINTER, a)) }
+    }
+
+    pub fn bool(a: bool) -> Self {
+        unsafe { Value(NanBox::new(BOOL, a as u8)) }
+    }
+
+    pub fn funk(address: u32) -> Self {
+        unsafe { Value(NanBox::new(FUNK, address)) }
+    }
+
+    pub fn nil() -> Self {
+        unsafe { Value(NanBox::new(NIL, 0u8)) }
+    }
+
+    pub fn unpack(self) -> Variant {
+        // every tag below was written by one of the constructors above, along with its payload
+        unsafe {
+            match self.0.tag() as u8 {
+                INT => Variant::Int(self.0.unpack::<u32>() as i32),
+                CHAR => Variant::Char(self.0.unpack()),
+                POINTER => Variant::Pointer(self.0.unpack()),
+                BOOL => Variant::Bool(self.0.unpack::<u8>() != 0),
+                NIL => Variant::Nil,
+                FUNK => Variant::Funk(self.0.unpack()),
+                _ => Variant::Float(self.0.unpack()),
+            }
+        }
+    }
+
+    // only two bit patterns are falsy, so this is a pair of comparisons rather than an unpack
+    pub fn truthy(&self) -> bool {
+        self.0 != Value::bool(false).0 && self.0 != Value::nil().0
+    }
+}
+
+impl From<Variant> for Value {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Float(a) => Value::float(a),
+            Variant::Bool(a) => Value::bool(a),
+            Variant::Int(a) => Value::int(a),
+            Variant::Char(a) => Value::char(a),
+            Variant::Pointer(a) => Value::pointer(a),
+            Variant::Funk(a) => Value::funk(a),
+            Variant::Nil => Value::nil(),
+        }
+    }
+}
+
+// compares like the unpacked values do, so `0.0 == -0.0` and NaN never equals itself
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.unpack() == other.unpack()
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.unpack())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(variant: Variant) -> Variant {
+        Value::from(variant).unpack()
+    }
+
+    #[test]
+    fn ints_round_trip() {
+        for &a in &[0, 1, -1, 42, -42, i32::MIN, i32::MAX] {
+            assert_eq!(round_trip(Variant::Int(a)), Variant::Int(a));
+        }
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        for &a in &[0.0, 1.5, -1.5, f64::MIN, f64::MAX, f64::MIN_POSITIVE, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(round_trip(Variant::Float(a)), Variant::Float(a));
+        }
+    }
+
+    #[test]
+    fn zeros_keep_their_sign() {
+        for &a in &[0.0f64, -0.0] {
+            match Value::float(a).unpack() {
+                Variant::Float(b) => assert_eq!(b.to_bits(), a.to_bits()),
+                other => panic!("expected a float, found {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn nans_are_made_canonical() {
+        // NaNs with a sign or payload bits set, which could otherwise pass for a tagged value
+        let payload = f64::from_bits(CANONICAL_NAN | 0x0007_0000_0000_0007);
+
+        for &a in &[f64::NAN, -f64::NAN, payload] {
+            match Value::float(a).unpack() {
+                Variant::Float(b) => assert_eq!(b.to_bits(), CANONICAL_NAN),
+                other => panic!("expected a float, found {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn chars_round_trip() {
+        for &a in &['\0', 'a', 'é', '\u{1f980}', char::MAX] {
+            assert_eq!(round_trip(Variant::Char(a)), Variant::Char(a));
+        }
+    }
+
+    #[test]
+    fn pointers_and_funks_round_trip() {
+        for &a in &[0, 1, u32::MAX] {
+            assert_eq!(round_trip(Variant::Pointer(a)), Variant::Pointer(a));
+            assert_eq!(round_trip(Variant::Funk(a)), Variant::Funk(a));
+        }
+    }
+
+    #[test]
+    fn bools_and_nil_round_trip() {
+        assert_eq!(round_trip(Variant::Bool(true)), Variant::Bool(true));
+        assert_eq!(round_trip(Variant::Bool(false)), Variant::Bool(false));
+        assert_eq!(round_trip(Variant::Nil), Variant::Nil);
+    }
+
+    #[test]
+    fn only_false_and_nil_are_falsy() {
+        assert!(!Value::bool(false).truthy());
+        assert!(!Value::nil().truthy());
+
+        let values = [
+            Value::bool(true),
+            Value::int(0),
+            Value::float(0.0),
+            Value::float(f64::NAN),
+            Value::char('\0'),
+            Value::pointer(0),
+            Value::funk(0),
+        ];
+
+        for value in &values {
+            assert!(value.truthy(), "{:?} should be truthy", value);
+        }
+    }
+}
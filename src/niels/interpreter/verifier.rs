@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+use super::*;
+use Response::Wrong;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VerifyErrorKind {
+    BadInstruction,
+    BadJump(u32),
+    BadFunk(u32),
+    BadConstant(u32),
+    BadLocal(u32),
+    StackUnderflow,
+    StackMismatch(usize, usize), // depth already seen here, depth arriving
+    ReturnOutsideFunction,
+    MissingReturn,
+    MisplacedEnter,
+}
+
+impl fmt::Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VerifyErrorKind::*;
+
+        match *self {
+            BadInstruction => write!(f, "bad instruction"),
+            BadJump(target) => write!(f, "jump to {:04x}, which isn't an instruction", target),
+            BadFunk(address) => write!(f, "no funk starts at {:04x}", address),
+            BadConstant(index) => write!(f, "constant #{} is missing or of the wrong kind", index),
+            BadLocal(n) => write!(f, "local {} is past the {} available slots", n, LOCAL_SLOTS),
+            StackUnderflow => write!(f, "stack underflow"),
+            StackMismatch(seen, arriving) => {
+                write!(f, "stack depth {} here on one path and {} on another", seen, arriving)
+            },
+            ReturnOutsideFunction => write!(f, "return outside of a function"),
+            MissingReturn => write!(f, "function runs off the end of the code"),
+            MisplacedEnter => write!(f, "funk entered without being called"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    pub offset: usize,
+}
+
+impl VerifyError {
+    // `file` is where the code came from, unless the program says otherwise
+    pub fn diagnostic(&self, program: &Program, file: &FilePath) -> Diagnostic {
+        let file = program.file(self.offset).map(|file| FilePath(file.to_string())).unwrap_or_else(|| file.clone());
+
+        Diagnostic::new(
+            Wrong(format!("invalid bytecode: {}", self.kind)),
+            &file,
+            program.position(self.offset).cloned(),
+        )
+        .with_note(format!("at offset {:04x}", self.offset))
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (offset {})", self.kind, self.offset)
+    }
+}
+
+// checks `program` can run without the VM tripping over its own bookkeeping: every jump
+// lands on an instruction, every funk starts with an `Enter`, constants and locals exist,
+// and each instruction is reached with the same stack depth along every path; a call gets
+// a stack of its own, so each funk is checked on its own too
+pub fn verify(program: &Program) -> Result<(), VerifyError> {
+    let verifier = Verifier {
+        program,
+        starts: starts(program)?,
+    };
+
+    let mut funks = Vec::new();
+
+    for export in &program.exports {
+        funks.push(verifier.funk(export.address, export.address as usize)?)
+    }
+
+    for method in program.types.iter().flat_map(|ty| &ty.methods) {
+        funks.push(verifier.funk(method.address, method.address as usize)?)
+    }
+
+    verifier.walk(None, &mut funks)?;
+
+    let mut done = HashSet::new();
+
+    while let Some(entry) = funks.pop() {
+        if done.insert(entry) {
+            verifier.walk(Some(entry), &mut funks)?
+        }
+    }
+
+    Ok(())
+}
+
+// the offset of every instruction
+fn starts(program: &Program) -> Result<Vec<usize>, VerifyError> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+
+    while offset < program.code.len() {
+        match program.decode(offset) {
+            Some((_, len)) => {
+                starts.push(offset);
+
+                offset += len
+            },
+
+            None => {
+                return Err(VerifyError {
+                    kind: VerifyErrorKind::BadInstruction,
+                    offset,
+                })
+            },
+        }
+    }
+
+    Ok(starts)
+}
+
+struct Verifier<'p> {
+    program: &'p Program,
+    starts: Vec<usize>,
+}
+
+impl<'p> Verifier<'p> {
+    // follows every path from a funk's entry, or the program's start when `entry` is nothing
+    fn walk(&self, entry: Option<usize>, funks: &mut Vec<usize>) -> Result<(), VerifyError> {
+        use self::OpCode::*;
+        use self::VerifyErrorKind::*;
+
+        let mut seen: HashMap<usize, usize> = HashMap::new();
+        let mut pending = vec!((entry.unwrap_or(0), 0));
+
+        while let Some((offset, depth)) = pending.pop() {
+            let error = |kind| VerifyError { kind, offset };
+
+            match seen.get(&offset) {
+                Some(&seen) if seen != depth => return Err(error(StackMismatch(seen, depth))),
+                Some(_) => continue,
+                None => seen.insert(offset, depth),
+            };
+
+            let (op, len) = match self.program.decode(offset) {
+                Some(decoded) => decoded,
+                None if entry.is_none() => continue,
+                None => return Err(error(MissingReturn)),
+            };
+
+            let take = |n: usize| depth.checked_sub(n).ok_or_else(|| error(StackUnderflow));
+
+            match op {
+                LoadFloat(index) => self.constant(index, offset, |c| matches!(c, Constant::Float(_)))?,
+                LoadString(index) | LoadField(index) | SetField(index) | Instance(index) => {
+                    self.constant(index, offset, |c| matches!(c, Constant::Str(_)))?
+                },
+                LoadRecord(index) => self.constant(index, offset, |c| matches!(c, Constant::Names(_)))?,
+                CallNative(index) => self.constant(index, offset, |c| matches!(c, Constant::Native(..)))?,
+                Closure(index) => self.constant(index, offset, |c| matches!(c, Constant::Captures(_)))?,
+                Invoke(index) => self.constant(index, offset, |c| matches!(c, Constant::Method(..)))?,
+                LoadLocal(n) | SetLocal(n) | LoadGlobal(n) | SetGlobal(n) if n as usize >= LOCAL_SLOTS => return Err(error(BadLocal(n))),
+                _ => (),
+            }
+
+            let next = offset + len;
+
+            let depth = match op {
+                Jmp(target) => {
+                    pending.push((self.jump(target, offset)?, depth));
+                    continue;
+                },
+
+                JmpIf(target) | JmpIfNot(target) => {
+                    let depth = take(1)?;
+
+                    pending.push((self.jump(target, offset)?, depth));
+
+                    depth
+                },
+
+                // pops the sequence and counter, pushing the element unless it's done
+                Next(target) => {
+                    let depth = take(2)?;
+
+                    pending.push((self.jump(target, offset)?, depth));
+
+                    depth + 1
+                },
+
+                LoadFunk(address) => {
+                    funks.push(self.funk(address, offset)?);
+
+                    depth + 1
+                },
+
+                Enter(_) if Some(offset) != entry => return Err(error(MisplacedEnter)),
+
+                Ret => {
+                    if entry.is_none() {
+                        return Err(error(ReturnOutsideFunction));
+                    }
+
+                    take(1)?;
+
+                    continue;
+                },
+
+                _ => {
+                    let (pops, pushes) = self.stack_effect(&op);
+
+                    take(pops)? + pushes
+                },
+            };
+
+            pending.push((next, depth))
+        }
+
+        Ok(())
+    }
+
+    // values popped and pushed by each instruction, when it falls through to the next one
+    fn stack_effect(&self, op: &OpCode) -> (usize, usize) {
+        use self::OpCode::*;
+
+        match *op {
+            LoadInt(_) | LoadFloat(_) | LoadChar(_) | LoadString(_) | LoadBool(_) | LoadNil | LoadLocal(_) => (0, 1),
+            LoadUpvalue(_) | LoadGlobal(_) | LoadFunk(_) => (0, 1),
+            LoadArray(len) | Format(len) => (len as usize, 1),
+            LoadRange(_) => (2, 1),
+            LoadRecord(index) => match self.program.constant(index) {
+                Some(Constant::Names(names)) => (names.len(), 1),
+                _ => (0, 1),
+            },
+            CallNative(index) => match self.program.constant(index) {
+                Some(&Constant::Native(_, arity)) => (arity as usize, 1),
+                _ => (0, 1),
+            },
+            LoadField(_) | LoadIndex(_) | Closure(_) | Instance(_) => (1, 1),
+            LoadElement => (2, 1),
+
+            Pop | SetLocal(_) | SetUpvalue(_) | SetGlobal(_) => (1, 0),
+            SetIndex(_) | SetField(_) => (2, 0),
+            SetElement => (3, 0),
+
+            Neg | Not => (1, 1),
+            Add | Sub | Mul | Div | Mod | Pow | Concat | Lt | Gt | Eq | NEq | LtEq | GtEq => (2, 1),
+
+            Enter(_) | Jmp(_) => (0, 0),
+            JmpIf(_) | JmpIfNot(_) | Ret => (1, 0),
+            Next(_) => (2, 1),
+            // whatever the callee leaves on its own stack is dropped once it returns
+            Call(argc) => (argc as usize + 1, 1),
+            Invoke(index) => match self.program.constant(index) {
+                Some(&Constant::Method(_, argc)) => (argc as usize + 1, 1),
+                _ => (0, 1),
+            },
+        }
+    }
+
+    fn constant<F: Fn(&Constant) -> bool>(&self, index: u32, offset: usize, kind: F) -> Result<(), VerifyError> {
+        match self.program.constant(index) {
+            Some(constant) if kind(constant) => Ok(()),
+            _ => Err(VerifyError {
+                kind: VerifyErrorKind::BadConstant(index),
+                offset,
+            }),
+        }
+    }
+
+    // jumping to the very end is allowed, and ends the program
+    fn jump(&self, target: u32, offset: usize) -> Result<usize, VerifyError> {
+        let target = target as usize;
+
+        if target == self.program.code.len() || self.is_start(target) {
+            Ok(target)
+        } else {
+            Err(VerifyError {
+                kind: VerifyErrorKind::BadJump(target as u32),
+                offset,
+            })
+        }
+    }
+
+    // the entry of the funk at `address`, which has to start with an `Enter`
+    fn funk(&self, address: u32, offset: usize) -> Result<usize, VerifyError> {
+        match self.program.decode(address as usize) {
+            Some((OpCode::Enter(_), _)) if self.is_start(address as usize) => Ok(address as usize),
+            _ => Err(VerifyError {
+                kind: VerifyErrorKind::BadFunk(address),
+                offset,
+            }),
+        }
+    }
+
+    fn is_start(&self, offset: usize) -> bool {
+        self.starts.binary_search(&offset).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::niels::parser::parse;
+
+    fn kind(asm: Assembler) -> Option<VerifyErrorKind> {
+        verify(&asm.program).err().map(|error| error.kind)
+    }
+
+    #[test]
+    fn jumps_have_to_land_on_an_instruction() {
+        let mut asm = Assembler::new();
+
+        asm.emit(OpCode::LoadInt(0));
+        asm.emit(OpCode::Pop);
+        asm.emit(OpCode::Jmp(1));
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::BadJump(1)));
+    }
+
+    #[test]
+    fn paths_have_to_agree_on_the_stack_depth() {
+        let mut asm = Assembler::new();
+        let end = asm.label();
+
+        asm.emit(OpCode::LoadBool(true));
+        asm.jump(OpCode::JmpIfNot, end);
+        asm.emit(OpCode::LoadInt(1));
+        asm.bind(end);
+
+        assert!(matches!(kind(asm), Some(VerifyErrorKind::StackMismatch(..))));
+    }
+
+    #[test]
+    fn funks_are_only_entered_by_calls() {
+        let mut asm = Assembler::new();
+
+        asm.emit(OpCode::Enter(0));
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::MisplacedEnter));
+    }
+
+    #[test]
+    fn funks_have_to_return() {
+        let mut asm = Assembler::new();
+        let funk = asm.label();
+        let end = asm.label();
+
+        asm.jump(OpCode::LoadFunk, funk);
+        asm.emit(OpCode::Pop);
+        asm.jump(OpCode::Jmp, end);
+
+        asm.bind(funk);
+        asm.emit(OpCode::Enter(0));
+        asm.emit(OpCode::LoadNil);
+        asm.bind(end);
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::MissingReturn));
+    }
+
+    #[test]
+    fn constants_have_to_exist_and_be_of_the_right_kind() {
+        let mut asm = Assembler::new();
+
+        asm.emit(OpCode::LoadFloat(0));
+        asm.emit(OpCode::Pop);
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::BadConstant(0)));
+
+        let mut asm = Assembler::new();
+
+        asm.load_float(1.5);
+        asm.emit(OpCode::LoadString(0));
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::BadConstant(0)));
+    }
+
+    #[test]
+    fn locals_have_to_fit_the_slots() {
+        let mut asm = Assembler::new();
+
+        asm.emit(OpCode::LoadLocal(LOCAL_SLOTS as u32));
+
+        assert_eq!(kind(asm), Some(VerifyErrorKind::BadLocal(LOCAL_SLOTS as u32)));
+    }
+
+    #[test]
+    fn compiled_programs_pass() {
+        let code = "
+implement Counter:
+    funk bump(by):
+        self.n = self.n + by
+        return self
+
+funk adder(a):
+    return |b| a + b
+
+counter = Counter { n: 0 }
+add = adder(2)
+
+for i in 0..3:
+    counter.bump(add(i))
+
+while counter.n > 0:
+    counter.n = counter.n - 1
+
+    if counter.n == 2:
+        break
+";
+
+        let source = Source::from("<test>", code.lines().map(String::from).collect());
+        let ast = parse(&source).unwrap();
+
+        let mut compiler = Compiler::new();
+
+        assert_eq!(verify(compiler.compile(&ast, &source).unwrap()), Ok(()));
+    }
+}
@@ -1,52 +1,160 @@
+
 use std::collections::HashMap;
 use std::fmt;
 
-use super::{ OpCode };
+use super::*;
+use Response::Wrong;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RuntimeErrorKind {
+    TypeMismatch,
+    StackUnderflow,
+    StackOverflow,
+    IndexOutOfRange,
+    DivisionByZero,
+    IntegerOverflow,
+    BadPointer,
+    UnknownField,
+    BadInstruction,
+    UnknownNative,
+    NotCallable,
+    NoMethod(String),
+    ArityMismatch(u32, u32), // parameters, arguments
+    Native(String), // raised by a native funk, with its own message
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RuntimeErrorKind::*;
+
+        match self {
+            TypeMismatch => write!(f, "type mismatch"),
+            StackUnderflow => write!(f, "stack underflow"),
+            StackOverflow => write!(f, "stack overflow"),
+            IndexOutOfRange => write!(f, "index out of range"),
+            DivisionByZero => write!(f, "division by zero"),
+            IntegerOverflow => write!(f, "integer overflow"),
+            BadPointer => write!(f, "bad pointer"),
+            UnknownField => write!(f, "unknown field"),
+            BadInstruction => write!(f, "bad instruction"),
+            UnknownNative => write!(f, "no such native funk"),
+            NotCallable => write!(f, "can only call funks"),
+            NoMethod(ref name) => write!(f, "no field or method `{}`", name),
+            ArityMismatch(params, args) => write!(f, "expected {} arguments, found {}", params, args),
+            Native(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub op: Option<OpCode>, // nothing if the instruction itself couldn't be decoded
+    pub ip: usize,
+    pub trace: Vec<usize>, // call sites of the active funks, innermost first
+}
+
+impl RuntimeError {
+    // an error not yet tied to an instruction, like one a native funk returns; the VM
+    // fills in where it happened
+    pub fn new(kind: RuntimeErrorKind) -> Self {
+        RuntimeError {
+            kind,
+            op: None,
+            ip: 0,
+            trace: Vec::new(),
+        }
+    }
+
+    // `file` is where the code came from, unless the program says otherwise
+    pub fn diagnostic(&self, program: &Program, file: &FilePath) -> Diagnostic {
+        let file_of = |ip| program.file(ip).map(|file| FilePath(file.to_string())).unwrap_or_else(|| file.clone());
+
+        let file = file_of(self.ip);
+
+        let mut diagnostic = Diagnostic::new(
+            Wrong(self.kind.to_string()),
+            &file,
+            program.position(self.ip).cloned(),
+        );
+
+        // runaway recursion would otherwise bury the error under its own trace
+        for &call in self.trace.iter().take(TRACE_LIMIT) {
+            if let Some(pos) = program.position(call) {
+                let caller = file_of(call);
+
+                // calls from another module say which
+                if caller.0 == file.0 {
+                    diagnostic = diagnostic.with_note(format!("called from here{}", pos))
+                } else {
+                    diagnostic = diagnostic.with_note(format!("called from here{}{}", caller, pos))
+                }
+            }
+        }
 
+        if self.trace.len() > TRACE_LIMIT {
+            diagnostic = diagnostic.with_note(format!("... and {} calls before that", self.trace.len() - TRACE_LIMIT))
+        }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Value {
-    Float(f64),
-    Bool(bool),
-    Int(i32),
-    Char(char),
-    Pointer(u32),
-    Nil,
+        diagnostic
+    }
 }
 
-impl Value {
-    pub fn truthy(&self) -> bool {
-        use self::Value::*;
-
-        match &self {
-            &Float(_) |
-            &Int(_)   |
-            &Char(_)  |
-            &Pointer(_) => true,
-            Bool(true)  => true,
-            _           => false,
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.op {
+            Some(op) => write!(f, "{} at `{:?}` (offset {})", self.kind, op, self.ip),
+            None => write!(f, "{} (offset {})", self.kind, self.ip),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum HeapValue {
-    Str(String),
-    Array(Vec<Value>),
+// locals of every frame share this many slots
+pub const LOCAL_SLOTS: usize = 10000;
+
+// calls deeper than this are taken to be runaway recursion
+pub const CALL_DEPTH: usize = 10000;
+
+// calls shown in a runtime error's trace
+const TRACE_LIMIT: usize = 8;
+
+// one per active call, the top level having none
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub callee: u32, // address of the funk's `Enter`
+    pub argc: u32,
+    pub base: usize,  // first local slot
+    pub ret: usize,   // where to continue once it returns
+    pub stack: usize, // operand stack height to return to
+
+    pub closure: Option<u32>, // the closure called, if it was one
+}
+
+// a funk provided by the host, given its arguments in order
+pub type NativeFn = fn(&mut VirtualMachine, &[Value]) -> Result<Value, RuntimeError>;
+
+#[derive(Clone, Copy)]
+pub struct Native {
+    pub arity: u32,
+    pub function: NativeFn,
 }
 
 #[derive(Clone)]
 pub struct VirtualMachine {
-    pub heap: Vec<HeapValue>,
+    pub heap: Heap,
 
     pub stack: Vec<Value>,
-    pub call_stack: Vec<usize>,
-    pub var_stack: [Value; 10000],
+    pub var_stack: [Value; LOCAL_SLOTS],
 
     pub var_top: usize,
     
-    pub frames: Vec<usize>,
+    pub frames: Vec<Frame>,
     pub ip: usize,
+
+    natives: HashMap<String, Native>,
+
+    // upvalues still pointing at a local slot, and the slot
+    open: Vec<(usize, u32)>,
 }
 
 
@@ -56,207 +164,1035 @@ unsafe impl Send for VirtualMachine {}
 impl VirtualMachine {
     pub fn new() -> Self {
         VirtualMachine {
-            heap:  Vec::with_capacity(10000),
+            heap: Heap::new(GcConfig::default()),
 
             stack: Vec::with_capacity(10000),
-            call_stack: Vec::with_capacity(10000),
-            var_stack: [Value::Nil; 10000],
+            var_stack: [Value::nil(); LOCAL_SLOTS],
 
-            frames: vec!(0),
+            frames: Vec::new(),
 
             var_top: 0,
 
             ip: 0,
+
+            natives: HashMap::new(),
+
+            open: Vec::new(),
+        }
+    }
+
+    // makes `function` callable from scripts as `name`, replacing any native of the same name;
+    // compilers need to be told about it too, see `Compiler::declare_native`
+    pub fn register_native(&mut self, name: &str, arity: u32, function: NativeFn) {
+        self.natives.insert(name.to_string(), Native { arity, function });
+    }
+
+    pub fn natives(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.natives.iter().map(|(name, native)| (name.as_str(), native.arity))
+    }
+
+    pub fn execute(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.reserve_globals(program);
+
+        while self.ip < program.code.len() {
+            let ip = self.ip;
+            let decoded = program.decode(ip);
+
+            // between instructions every live value is reachable from the roots
+            if self.heap.should_collect() {
+                self.collect_garbage()
+            }
+
+            let result = match decoded {
+                Some((op, len)) => {
+                    self.ip += len;
+
+                    self.execute_op(&op, program)
+                },
+
+                None => Err(RuntimeErrorKind::BadInstruction),
+            };
+
+            if let Err(kind) = result {
+                // return addresses point just past their call
+                let trace = self.frames.iter().rev().map(|frame| frame.ret - 1).collect();
+
+                return Err(RuntimeError {
+                    kind,
+                    op: decoded.map(|(op, _)| op),
+                    ip,
+                    trace,
+                });
+            }
         }
+
+        Ok(())
+    }
+
+    // keeps the slots of `program`'s globals out of reach of the locals of calls, as a global
+    // can be declared long before it's assigned
+    pub fn reserve_globals(&mut self, program: &Program) {
+        self.var_top = self.var_top.max(program.globals as usize)
     }
 
-    pub fn execute(&mut self, program: &[OpCode]) {
-        while self.ip < program.len() {
-            self.execute_op(&program[self.ip]);
+    // calls the funk under the top `argc` values with them as its arguments, which become
+    // its first locals; its `Enter` checks there's the right number of them
+    pub fn call(&mut self, argc: u32, ret: usize) -> Result<(), RuntimeErrorKind> {
+        let at = self.stack.len().checked_sub(argc as usize + 1).ok_or(RuntimeErrorKind::StackUnderflow)?;
+
+        let (callee, closure) = match self.stack[at].unpack() {
+            Variant::Funk(address) => (address, None),
+            Variant::Pointer(pointer) => match self.heap.get(pointer) {
+                Some(&HeapValue::Closure(address, _)) => (address, Some(pointer)),
+                _ => return Err(RuntimeErrorKind::NotCallable),
+            },
+            _ => return Err(RuntimeErrorKind::NotCallable),
+        };
+
+        let base = self.var_top;
+
+        if self.frames.len() >= CALL_DEPTH || base + argc as usize > LOCAL_SLOTS {
+            return Err(RuntimeErrorKind::StackOverflow);
+        }
+
+        for (i, arg) in self.stack.drain(at + 1 ..).enumerate() {
+            self.var_stack[base + i] = arg
+        }
+
+        self.stack.pop();
+
+        self.var_top = base + argc as usize;
+
+        self.frames.push(Frame {
+            callee,
+            argc,
+            base,
+            ret,
+            stack: at,
+            closure,
+        });
+
+        self.ip = callee as usize;
 
-            self.ip += 1
+        Ok(())
+    }
+
+    // drops whatever a failed `execute` left behind, keeping the top-level locals and the heap
+    pub fn unwind(&mut self) {
+        if let Some(frame) = self.frames.first() {
+            self.truncate_locals(frame.base)
         }
+
+        self.frames.clear();
+        self.stack.clear();
     }
 
-    pub fn execute_op(&mut self, op: &OpCode) {
+    // the roots are the operand stack and every frame's locals, top-level ones included
+    pub fn collect_garbage(&mut self) {
+        // a called closure is off the stack while it runs, and open upvalues may be
+        // waiting for a closure that hasn't been created yet
+        let closures = self.frames.iter().filter_map(|frame| frame.closure);
+        let upvalues = self.open.iter().map(|&(_, pointer)| pointer);
+
+        let pointers = closures.chain(upvalues).map(Value::pointer).collect::<Vec<Value>>();
+
+        let roots = self.stack.iter().chain(self.var_stack[.. self.var_top].iter()).chain(pointers.iter());
+
+        self.heap.collect(roots)
+    }
+
+    pub fn execute_op(&mut self, op: &OpCode, program: &Program) -> Result<(), RuntimeErrorKind> {
         use self::OpCode::*;
+        use self::RuntimeErrorKind::*;
 
         macro_rules! binop {
             ($($pat:pat => $block:block)+) => {{
-                let _b = self.pop();
-                let _a = self.pop();
+                let _b = self.pop()?;
+                let _a = self.pop()?;
 
-                let _result = match (_b, _a) {
+                let _result = match (_a.unpack(), _b.unpack()) {
                     $($pat => $block)+,
-                    _ => panic!("Invalid operands"),
+                    _ => return Err(TypeMismatch),
                 };
-                self.push(_result);
+                self.push(Value::from(_result));
             }}
         }
 
         match op {
-            LoadInt(ref a) => self.push(Value::Int(*a)),
-            LoadFloat(ref a) => self.push(Value::Float(*a)),
-            LoadBool(ref a) => self.push(Value::Bool(*a)),
-            LoadChar(ref a) => self.push(Value::Char(*a)),
-            LoadString(ref a) => {
-                self.heap.push(HeapValue::Str(a.to_owned()));
-                self.push(Value::Pointer(self.heap.len() as u32))
+            LoadInt(ref a) => self.push(Value::int(*a)),
+            LoadFloat(index) => match program.constant(*index) {
+                Some(&Constant::Float(a)) => self.push(Value::float(a)),
+                _ => return Err(BadInstruction),
+            },
+            LoadBool(ref a) => self.push(Value::bool(*a)),
+            LoadChar(ref a) => self.push(Value::char(*a)),
+            LoadString(index) => {
+                let content = match program.constant(*index) {
+                    Some(Constant::Str(content)) => content.clone(),
+                    _ => return Err(BadInstruction),
+                };
+
+                let pointer = self.alloc(HeapValue::Str(content));
+
+                self.push(pointer)
             },
+            LoadNil => self.push(Value::nil()),
             LoadArray(ref len) => {
                 let mut content = Vec::new();
 
                 for _ in 0 .. *len {
-                    content.push(self.pop())
+                    content.push(self.pop()?)
                 }
 
-                self.heap.push(HeapValue::Array(content));
-                self.push(Value::Pointer((self.heap.len() - 1) as u32))
+                content.reverse();
+
+                let pointer = self.alloc(HeapValue::Array(content));
+
+                self.push(pointer)
             },
-            LoadIndex(i) => {
-                let pointer = self.pop();
+            Format(ref len) => {
+                let mut string = String::new();
 
-                if let Value::Pointer(ref heap_ref) = pointer {
-                    if let HeapValue::Array(ref content) = self.heap[*heap_ref as usize] {
-                        self.push(content[*i as usize].clone());
-                    }
+                for part in self.stack.len().saturating_sub(*len as usize) .. self.stack.len() {
+                    string.push_str(&self.display(&self.stack[part]))
                 }
+
+                for _ in 0 .. *len {
+                    self.pop()?;
+                }
+
+                let pointer = self.alloc(HeapValue::Str(string));
+
+                self.push(pointer)
+            },
+            LoadRange(inclusive) => {
+                let end   = self.pop()?;
+                let start = self.pop()?;
+
+                let range = match (start.unpack(), end.unpack()) {
+                    (Variant::Int(start), Variant::Int(end)) => HeapValue::Range(start, end, *inclusive),
+                    _ => return Err(TypeMismatch),
+                };
+
+                let pointer = self.alloc(range);
+
+                self.push(pointer)
+            },
+            LoadRecord(index) => {
+                let names = match program.constant(*index) {
+                    Some(Constant::Names(names)) => names,
+                    _ => return Err(BadInstruction),
+                };
+
+                let mut fields = Vec::new();
+
+                for name in names.iter().rev() {
+                    fields.push((name.clone(), self.pop()?))
+                }
+
+                fields.reverse();
+
+                let pointer = self.alloc(HeapValue::Record(None, fields));
+
+                self.push(pointer)
+            },
+            Instance(index) => {
+                let name   = Self::name(program, *index)?;
+                let record = self.pop()?;
+
+                match *self.heap_value_mut(record)? {
+                    HeapValue::Record(ref mut ty, _) => *ty = Some(name.to_string()),
+                    _ => return Err(TypeMismatch),
+                }
+
+                self.push(record)
+            },
+            LoadField(index) => {
+                let name   = Self::name(program, *index)?;
+                let record = self.pop()?;
+                let value  = *self.field(record, name)?;
+
+                self.push(value)
+            },
+            LoadIndex(i) => {
+                let pointer = self.pop()?;
+
+                let element = match *self.heap_value(pointer)? {
+                    HeapValue::Array(ref content) => *content.get(*i as usize).ok_or(IndexOutOfRange)?,
+                    _ => return Err(TypeMismatch),
+                };
+
+                self.push(element)
+            },
+            LoadElement => {
+                let index    = self.pop()?;
+                let sequence = self.pop()?;
+
+                let element = match index.unpack() {
+                    Variant::Int(i) if i >= 0 => self.element(sequence, i as usize)?.ok_or(IndexOutOfRange)?,
+                    Variant::Int(_) => return Err(IndexOutOfRange),
+                    Variant::Pointer(_) => self.slice(sequence, index)?,
+                    _ => return Err(TypeMismatch),
+                };
+
+                self.push(element)
             },
             LoadLocal(n) => {
-                let value = self.var_stack[self.current_frame() + *n as usize];
+                let index = self.base() + *n as usize;
+                let value = *self.var_stack.get(index).ok_or(StackOverflow)?;
 
                 self.push(value)
             },
             SetLocal(n) => {
-                let value = self.pop();
+                let value = self.pop()?;
+                let index = self.base() + *n as usize;
+
+                *self.var_stack.get_mut(index).ok_or(StackOverflow)? = value;
+
+                if index >= self.var_top {
+                    self.var_top = index + 1
+                }
+            },
+            SetUpvalue(index) => {
+                let value = self.pop()?;
+
+                match *self.upvalue(*index)? {
+                    Upvalue::Open(slot) => self.var_stack[slot] = value,
+                    Upvalue::Closed(_) => *self.upvalue_mut(*index)? = Upvalue::Closed(value),
+                }
+            },
+            SetGlobal(slot) => {
+                let value = self.pop()?;
+
+                *self.var_stack.get_mut(*slot as usize).ok_or(StackOverflow)? = value;
 
-                self.var_stack[self.current_frame() + *n as usize] = value
+                if *slot as usize >= self.var_top {
+                    self.var_top = *slot as usize + 1
+                }
             },
             SetIndex(i) => {
-                let value   = self.pop();
-                let pointer = self.pop();
+                let value   = self.pop()?;
+                let pointer = self.pop()?;
 
-                if let Value::Pointer(ref heap_ref) = pointer {
-                    if let HeapValue::Array(ref mut content) = self.heap[*heap_ref as usize] {
-                        content[*i as usize] = value
-                    }
+                match *self.heap_value_mut(pointer)? {
+                    HeapValue::Array(ref mut content) => {
+                        *content.get_mut(*i as usize).ok_or(IndexOutOfRange)? = value
+                    },
+                    _ => return Err(TypeMismatch),
                 }
             },
+            SetElement => {
+                let value   = self.pop()?;
+                let index   = self.pop()?;
+                let pointer = self.pop()?;
+
+                let i = match index.unpack() {
+                    Variant::Int(i) if i >= 0 => i as usize,
+                    Variant::Int(_) => return Err(IndexOutOfRange),
+                    _ => return Err(TypeMismatch),
+                };
+
+                match *self.heap_value_mut(pointer)? {
+                    HeapValue::Array(ref mut content) => {
+                        *content.get_mut(i).ok_or(IndexOutOfRange)? = value
+                    },
+                    _ => return Err(TypeMismatch),
+                }
+            },
+            SetField(index) => {
+                let name   = Self::name(program, *index)?;
+                let value  = self.pop()?;
+                let record = self.pop()?;
+
+                *self.field(record, name)? = value
+            },
+            Pop => {
+                self.pop()?;
+            },
             Jmp(n) => {
                 self.ip = *n as usize
             },
             JmpIf(n) => {
-                let condition = self.pop();
+                let condition = self.pop()?;
 
                 if condition.truthy() {
                     self.ip = *n as usize
                 }
             },
-            Call(ret) => {
-                self.call_stack.push(self.ip);
-                self.ip = *ret as usize
+            JmpIfNot(n) => {
+                let condition = self.pop()?;
+
+                if !condition.truthy() {
+                    self.ip = *n as usize
+                }
+            },
+            Next(n) => {
+                let index    = self.pop()?;
+                let sequence = self.pop()?;
+
+                let i = match index.unpack() {
+                    Variant::Int(i) if i >= 0 => i as usize,
+                    _ => return Err(TypeMismatch),
+                };
+
+                match self.element(sequence, i)? {
+                    Some(element) => self.push(element),
+                    None => self.ip = *n as usize,
+                }
+            },
+            LoadFunk(address) => self.push(Value::funk(*address)),
+            LoadUpvalue(index) => {
+                let value = match *self.upvalue(*index)? {
+                    Upvalue::Open(slot) => self.var_stack[slot],
+                    Upvalue::Closed(value) => value,
+                };
+
+                self.push(value)
+            },
+            LoadGlobal(slot) => {
+                let value = *self.var_stack.get(*slot as usize).ok_or(StackOverflow)?;
+
+                self.push(value)
+            },
+            Closure(index) => {
+                let captures = match program.constant(*index) {
+                    Some(Constant::Captures(captures)) => captures,
+                    _ => return Err(BadInstruction),
+                };
+
+                let address = match self.pop()?.unpack() {
+                    Variant::Funk(address) => address,
+                    _ => return Err(TypeMismatch),
+                };
+
+                let mut upvalues = Vec::new();
+
+                for capture in captures {
+                    let pointer = match *capture {
+                        Capture::Local(slot) => self.capture(self.base() + slot as usize)?,
+                        Capture::Upvalue(index) => self.upvalue_pointer(index)?,
+                    };
+
+                    upvalues.push(pointer)
+                }
+
+                let closure = self.alloc(HeapValue::Closure(address, upvalues));
+
+                self.push(closure)
+            },
+            Enter(arity) => {
+                let frame = self.frames.last().ok_or(NotCallable)?;
+
+                if frame.argc != *arity {
+                    return Err(ArityMismatch(*arity, frame.argc));
+                }
+            },
+            Call(argc) => {
+                let ret = self.ip;
+
+                self.call(*argc, ret)?
+            },
+            // a funk kept in a field is called as it is, a method gets the receiver as `self`
+            Invoke(index) => {
+                let (name, argc) = match program.constant(*index) {
+                    Some(Constant::Method(name, argc)) => (name, *argc),
+                    _ => return Err(BadInstruction),
+                };
+
+                let at = self.stack.len().checked_sub(argc as usize + 1).ok_or(StackUnderflow)?;
+                let ret = self.ip;
+
+                let receiver = self.stack[at];
+
+                if let Ok(&mut field) = self.field(receiver, name) {
+                    self.stack[at] = field;
+
+                    return self.call(argc, ret);
+                }
+
+                let method = match receiver.unpack() {
+                    Variant::Pointer(pointer) => match self.heap.get(pointer) {
+                        Some(HeapValue::Record(Some(ty), _)) => program.method(ty, name),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                let (address, arity) = method.map(|method| (method.address, method.arity)).ok_or_else(|| NoMethod(name.clone()))?;
+
+                // counted here, so `self` isn't
+                if arity != argc + 1 {
+                    return Err(ArityMismatch(arity.saturating_sub(1), argc));
+                }
+
+                self.stack.insert(at, Value::funk(address));
+                self.call(argc + 1, ret)?
             },
             Ret => {
-                self.ip = self.call_stack.pop().unwrap()
+                let value = self.pop()?;
+                let frame = self.frames.pop().ok_or(StackUnderflow)?;
+
+                self.truncate_locals(frame.base);
+                self.stack.truncate(frame.stack);
+
+                self.push(value);
+
+                self.ip = frame.ret
             },
-            PushFrame => {
-                self.push_frame()
+            CallNative(index) => {
+                let native = match program.constant(*index) {
+                    Some(Constant::Native(name, arity)) => match self.natives.get(name) {
+                        Some(native) if native.arity == *arity => *native,
+                        _ => return Err(UnknownNative),
+                    },
+                    _ => return Err(BadInstruction),
+                };
+
+                let len = self.stack.len().checked_sub(native.arity as usize).ok_or(StackUnderflow)?;
+                let args = self.stack.split_off(len);
+
+                // the arguments are off the stack, but no collection runs until the next instruction
+                let result = (native.function)(self, &args).map_err(|error| error.kind)?;
+
+                self.push(result)
+            },
+
+            Neg => {
+                let value = match self.pop()?.unpack() {
+                    Variant::Int(a)   => Value::int(a.checked_neg().ok_or(IntegerOverflow)?),
+                    Variant::Float(a) => Value::float(-a),
+                    _ => return Err(TypeMismatch),
+                };
+
+                self.push(value)
+            },
+            Not => {
+                let value = self.pop()?;
+
+                self.push(Value::bool(!value.truthy()))
             },
-            PopFrame => {
-                self.var_top = self.pop_frame()
-            }
 
 
             // TODO: less ugly
             Add => {
                 binop! {
-                    (Value::Int(a), Value::Int(b))     => { Value::Int(a + b) }
-                    (Value::Float(a), Value::Float(b)) => { Value::Float(a + b) }
-                    (Value::Int(a), Value::Float(b))   => { Value::Float(a as f64 + b) }
-                    (Value::Float(a), Value::Int(b))   => { Value::Float(a + b as f64) }
+                    (Variant::Int(a), Variant::Int(b))     => { Variant::Int(a.checked_add(b).ok_or(IntegerOverflow)?) }
+                    (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a + b) }
+                    (Variant::Int(a), Variant::Float(b))   => { Variant::Float(a as f64 + b) }
+                    (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a + b as f64) }
                 }
             },
             Sub => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Int(a - b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Float(a - b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Float(a as f64 - b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Float(a - b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Int(a.checked_sub(b).ok_or(IntegerOverflow)?) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a - b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Float(a as f64 - b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a - b as f64) }
             },
             Mul => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Int(a * b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Float(a * b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Float(a as f64 * b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Float(a * b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Int(a.checked_mul(b).ok_or(IntegerOverflow)?) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a * b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Float(a as f64 * b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a * b as f64) }
             },
             Div => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Int(a / b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Float(a / b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Float(a as f64 / b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Float(a / b as f64) }
+                (Variant::Int(_), Variant::Int(0))     => { return Err(DivisionByZero) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Int(a.checked_div(b).ok_or(IntegerOverflow)?) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a / b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Float(a as f64 / b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a / b as f64) }
             },
             Mod => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Int(a % b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Float(a % b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Float(a as f64 % b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Float(a % b as f64) }
-            },
-            Eq => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a == b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a == b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool(a as f64 == b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a == b as f64) }
-            },
-            NEq => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a != b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a != b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool(a as f64 != b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a != b as f64) }
+                (Variant::Int(_), Variant::Int(0))     => { return Err(DivisionByZero) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Int(a.checked_rem(b).ok_or(IntegerOverflow)?) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a % b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Float(a as f64 % b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a % b as f64) }
+            },
+            // a negative exponent makes a fraction, so ints only stay ints for the others
+            Pow => binop! {
+                (Variant::Int(a), Variant::Int(b)) => {
+                    if b < 0 {
+                        Variant::Float((a as f64).powi(b))
+                    } else {
+                        Variant::Int(a.checked_pow(b as u32).ok_or(IntegerOverflow)?)
+                    }
+                }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Float(a.powf(b)) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Float((a as f64).powf(b)) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Float(a.powi(b)) }
+            },
+            Concat => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+
+                let joined = match (self.heap_value(a)?, self.heap_value(b)?) {
+                    (HeapValue::Str(a), HeapValue::Str(b)) => HeapValue::Str(format!("{}{}", a, b)),
+                    (HeapValue::Array(a), HeapValue::Array(b)) => HeapValue::Array(a.iter().chain(b).cloned().collect()),
+                    _ => return Err(TypeMismatch),
+                };
+
+                let pointer = self.alloc(joined);
+
+                self.push(pointer)
+            },
+            Eq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+
+                let result = self.equals(a, b);
+                self.push(Value::bool(result))
+            },
+            NEq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+
+                let result = self.equals(a, b);
+                self.push(Value::bool(!result))
             },
             Lt => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a < b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a < b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool((a as f64) < b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a < b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Bool(a < b) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Bool(a < b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Bool((a as f64) < b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Bool(a < b as f64) }
             },
             Gt => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a > b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a > b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool(a as f64 > b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a > b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Bool(a > b) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Bool(a > b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Bool(a as f64 > b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Bool(a > b as f64) }
             },
             LtEq => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a <= b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a <= b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool(a as f64 <= b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a <= b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Bool(a <= b) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Bool(a <= b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Bool(a as f64 <= b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Bool(a <= b as f64) }
             },
             GtEq => binop! {
-                (Value::Int(a), Value::Int(b))     => { Value::Bool(a >= b) }
-                (Value::Float(a), Value::Float(b)) => { Value::Bool(a >= b) }
-                (Value::Int(a), Value::Float(b))   => { Value::Bool(a as f64 >= b) }
-                (Value::Float(a), Value::Int(b))   => { Value::Bool(a >= b as f64) }
+                (Variant::Int(a), Variant::Int(b))     => { Variant::Bool(a >= b) }
+                (Variant::Float(a), Variant::Float(b)) => { Variant::Bool(a >= b) }
+                (Variant::Int(a), Variant::Float(b))   => { Variant::Bool(a as f64 >= b) }
+                (Variant::Float(a), Variant::Int(b))   => { Variant::Bool(a >= b as f64) }
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn display(&self, value: &Value) -> String {
+        match value.unpack() {
+            Variant::Float(a) => format!("{:?}", a),
+            Variant::Bool(a) => a.to_string(),
+            Variant::Int(a) => a.to_string(),
+            Variant::Char(a) => a.to_string(),
+            Variant::Nil => String::from("nil"),
+            Variant::Funk(address) => format!("<funk at {:04x}>", address),
+            Variant::Pointer(heap_ref) => match self.heap.get(heap_ref) {
+                Some(HeapValue::Str(ref content)) => content.clone(),
+                Some(HeapValue::Array(ref content)) => {
+                    let elements = content
+                        .iter()
+                        .map(|element| self.display(element))
+                        .collect::<Vec<String>>();
+
+                    format!("[{}]", elements.join(", "))
+                },
+                Some(&HeapValue::Range(start, end, inclusive)) => {
+                    format!("{}{}{}", start, if inclusive { "..." } else { ".." }, end)
+                },
+                Some(HeapValue::Record(ref ty, ref fields)) => {
+                    let fields = fields
+                        .iter()
+                        .map(|(name, value)| format!("{}: {}", name, self.display(value)))
+                        .collect::<Vec<String>>();
+
+                    match *ty {
+                        Some(ref ty) => format!("{} {{ {} }}", ty, fields.join(", ")),
+                        None => format!("{{ {} }}", fields.join(", ")),
+                    }
+                },
+                Some(&HeapValue::Closure(address, _)) => format!("<funk at {:04x}>", address),
+                Some(HeapValue::Upvalue(_)) => String::from("<upvalue>"),
+                None => String::from("<bad pointer>"),
             },
-            _ => (),
         }
     }
 
+    fn equals(&self, a: Value, b: Value) -> bool {
+        match (a.unpack(), b.unpack()) {
+            (Variant::Int(a), Variant::Float(b)) => a as f64 == b,
+            (Variant::Float(a), Variant::Int(b)) => a == b as f64,
+            (Variant::Pointer(a), Variant::Pointer(b)) => {
+                a == b || self.heap.get(a) == self.heap.get(b)
+            },
+            (a, b) => a == b,
+        }
+    }
+
+    fn name(program: &Program, index: u32) -> Result<&str, RuntimeErrorKind> {
+        match program.constant(index) {
+            Some(Constant::Str(name)) => Ok(name),
+            _ => Err(RuntimeErrorKind::BadInstruction),
+        }
+    }
+
+    fn field(&mut self, record: Value, name: &str) -> Result<&mut Value, RuntimeErrorKind> {
+        match *self.heap_value_mut(record)? {
+            HeapValue::Record(_, ref mut fields) => fields
+                .iter_mut()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value)
+                .ok_or(RuntimeErrorKind::UnknownField),
+
+            _ => Err(RuntimeErrorKind::TypeMismatch),
+        }
+    }
+
+    // see `Heap::set_config`
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_config(config)
+    }
+
+    pub fn alloc(&mut self, value: HeapValue) -> Value {
+        Value::pointer(self.heap.alloc(value))
+    }
+
+    // the `i`th element of an array, string or range, if it has one
+    fn element(&self, sequence: Value, i: usize) -> Result<Option<Value>, RuntimeErrorKind> {
+        let element = match *self.heap_value(sequence)? {
+            HeapValue::Array(ref content) => content.get(i).cloned(),
+            HeapValue::Str(ref content) => content.chars().nth(i).map(Value::char),
+            HeapValue::Record(..) | HeapValue::Closure(..) | HeapValue::Upvalue(_) => {
+                return Err(RuntimeErrorKind::TypeMismatch)
+            },
+            HeapValue::Range(start, end, inclusive) => {
+                let element = start as i64 + i as i64;
+
+                if element < HeapValue::range_end(end, inclusive) {
+                    Some(Value::int(element as i32))
+                } else {
+                    None
+                }
+            },
+        };
+
+        Ok(element)
+    }
+
+    // copies the part of an array or string covered by a range into a new heap value
+    fn slice(&mut self, sequence: Value, range: Value) -> Result<Value, RuntimeErrorKind> {
+        let (start, end) = match *self.heap_value(range)? {
+            HeapValue::Range(start, end, inclusive) => (start as i64, HeapValue::range_end(end, inclusive)),
+            _ => return Err(RuntimeErrorKind::TypeMismatch),
+        };
+
+        if start < 0 || start > end {
+            return Err(RuntimeErrorKind::IndexOutOfRange);
+        }
+
+        let bounds = start as usize .. end as usize;
+
+        let sliced = match *self.heap_value(sequence)? {
+            HeapValue::Array(ref content) => {
+                HeapValue::Array(content.get(bounds).ok_or(RuntimeErrorKind::IndexOutOfRange)?.to_vec())
+            },
+
+            HeapValue::Str(ref content) => {
+                let chars = content.chars().collect::<Vec<char>>();
+
+                HeapValue::Str(chars.get(bounds).ok_or(RuntimeErrorKind::IndexOutOfRange)?.iter().collect())
+            },
+
+            _ => return Err(RuntimeErrorKind::TypeMismatch),
+        };
+
+        Ok(self.alloc(sliced))
+    }
+
     fn push(&mut self, v: Value) {
         self.stack.push(v)
     }
 
-    fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap()
+    fn pop(&mut self) -> Result<Value, RuntimeErrorKind> {
+        self.stack.pop().ok_or(RuntimeErrorKind::StackUnderflow)
+    }
+
+    fn heap_value(&self, pointer: Value) -> Result<&HeapValue, RuntimeErrorKind> {
+        match pointer.unpack() {
+            Variant::Pointer(heap_ref) => self.heap.get(heap_ref).ok_or(RuntimeErrorKind::BadPointer),
+            _ => Err(RuntimeErrorKind::TypeMismatch),
+        }
+    }
+
+    fn heap_value_mut(&mut self, pointer: Value) -> Result<&mut HeapValue, RuntimeErrorKind> {
+        match pointer.unpack() {
+            Variant::Pointer(heap_ref) => self.heap.get_mut(heap_ref).ok_or(RuntimeErrorKind::BadPointer),
+            _ => Err(RuntimeErrorKind::TypeMismatch),
+        }
+    }
+
+    // the upvalue for a local slot, shared with any closure that captured it already
+    fn capture(&mut self, slot: usize) -> Result<u32, RuntimeErrorKind> {
+        if slot >= LOCAL_SLOTS {
+            return Err(RuntimeErrorKind::StackOverflow);
+        }
+
+        if let Some(&(_, pointer)) = self.open.iter().find(|&&(open, _)| open == slot) {
+            return Ok(pointer);
+        }
+
+        let pointer = self.heap.alloc(HeapValue::Upvalue(Upvalue::Open(slot)));
+
+        self.open.push((slot, pointer));
+
+        // a captured local is live even before it's set, so a call can't take its slot
+        if slot >= self.var_top {
+            self.var_top = slot + 1
+        }
+
+        Ok(pointer)
+    }
+
+    fn upvalue_pointer(&self, index: u32) -> Result<u32, RuntimeErrorKind> {
+        let closure = self.frames.last().and_then(|frame| frame.closure).ok_or(RuntimeErrorKind::BadInstruction)?;
+
+        match self.heap.get(closure) {
+            Some(HeapValue::Closure(_, upvalues)) => {
+                upvalues.get(index as usize).cloned().ok_or(RuntimeErrorKind::BadInstruction)
+            },
+            _ => Err(RuntimeErrorKind::BadPointer),
+        }
+    }
+
+    fn upvalue(&self, index: u32) -> Result<&Upvalue, RuntimeErrorKind> {
+        match self.heap.get(self.upvalue_pointer(index)?) {
+            Some(HeapValue::Upvalue(upvalue)) => Ok(upvalue),
+            _ => Err(RuntimeErrorKind::BadPointer),
+        }
+    }
+
+    fn upvalue_mut(&mut self, index: u32) -> Result<&mut Upvalue, RuntimeErrorKind> {
+        let pointer = self.upvalue_pointer(index)?;
+
+        match self.heap.get_mut(pointer) {
+            Some(HeapValue::Upvalue(upvalue)) => Ok(upvalue),
+            _ => Err(RuntimeErrorKind::BadPointer),
+        }
+    }
+
+    // clears the dropped locals so stale pointers don't keep garbage alive, first moving any
+    // that were captured into their upvalues
+    fn truncate_locals(&mut self, base: usize) {
+        let mut i = 0;
+
+        while i < self.open.len() {
+            let (slot, pointer) = self.open[i];
+
+            if slot >= base {
+                if let Some(HeapValue::Upvalue(upvalue)) = self.heap.get_mut(pointer) {
+                    *upvalue = Upvalue::Closed(self.var_stack[slot])
+                }
+
+                self.open.swap_remove(i);
+            } else {
+                i += 1
+            }
+        }
+
+        for local in &mut self.var_stack[base .. self.var_top] {
+            *local = Value::nil()
+        }
+
+        self.var_top = base
+    }
+
+    // the first local slot of the active call
+    fn base(&self) -> usize {
+        self.frames.last().map(|frame| frame.base).unwrap_or(0)
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        VirtualMachine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::niels::engine::Engine;
+    use crate::niels::error::Response;
+    use crate::niels::interpreter::heap::GcConfig;
+
+    #[test]
+    fn calls_keep_clear_of_unassigned_globals() {
+        let mut engine = Engine::new();
+
+        // `g` has a slot from the start, but nothing is stored in it until `f` runs
+        let t: i32 = engine.eval("if false:\n    g = 0\nfunk f():\n    t = 7\n    g = 1\n    return t\nf()").unwrap();
+
+        assert_eq!(t, 7);
+        assert_eq!(engine.global::<i32>("g"), Some(1));
+    }
+
+    #[test]
+    fn calls_keep_clear_of_globals_assigned_in_a_loop() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("for i in 0..0:\n    h = i\npub funk f(a):\n    u = a\n    h = 5\n    return u").unwrap();
+
+        assert_eq!(engine.call::<_, i32>("f", (3,)).unwrap(), 3);
+        assert_eq!(engine.global::<i32>("h"), Some(5));
     }
 
-    fn current_frame(&self) -> &usize {
-        self.frames.last().unwrap()
+    #[test]
+    fn a_counter_outlives_the_frame_it_was_made_in() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("funk counter():\n    n = 0\n    funk inc():\n        n = n + 1\n        return n\n    return inc\nc = counter()\nd = counter()\nc()\nc()").unwrap();
+
+        assert_eq!(engine.eval::<i32>("c()").unwrap(), 3);
+        assert_eq!(engine.eval::<i32>("d()").unwrap(), 1);
     }
 
-    fn push_frame(&mut self) {
-        self.frames.push(self.var_top)
+    #[test]
+    fn closures_share_a_captured_upvalue() {
+        let mut engine = Engine::new();
+
+        let v: i32 = engine.eval("funk pair():\n    v = 0\n    get = || v\n    funk set(x):\n        v = x\n    return [get, set]\np = pair()\np[1](42)\np[0]()").unwrap();
+
+        assert_eq!(v, 42);
     }
 
-    fn pop_frame(&mut self) -> usize {
-        self.frames.pop().unwrap()
+    #[test]
+    fn lambdas_capture_through_nested_funks() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<i32>("funk adder(x):\n    return |y| x + y\nadder(5)(10)").unwrap(), 15);
+        assert_eq!(engine.eval::<i32>("funk outer(a):\n    funk middle(b):\n        return |c| a + b + c\n    return middle\nouter(1)(2)(3)").unwrap(), 6);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn captured_upvalues_survive_collections() {
+        let mut engine = Engine::new();
+
+        engine.set_gc_config(GcConfig { threshold: 8, growth: 1.0 });
+
+        let total: i32 = engine.eval("funk make(i):\n    s = [i, i + 1]\n    return || s[0] + s[1]\nkeep = make(100)\ntotal = 0\nfor i in 0..200:\n    junk = [i, i, i]\n    total = total + make(i)()\ntotal + keep()").unwrap();
+
+        assert_eq!(total, 40201);
+        assert!(engine.gc_stats().collections > 0);
+    }
+
+    fn wrong(code: &str) -> String {
+        match Engine::new().eval::<()>(code).unwrap_err()[0].response {
+            Response::Wrong(ref message) => message.clone(),
+            ref other => panic!("expected an error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ranges_slice_strings_by_char() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<String>("\"h\u{e9}llo\"[1..3]").unwrap(), "\u{e9}l");
+        assert_eq!(engine.eval::<String>("\"h\u{e9}llo\"[1...3]").unwrap(), "\u{e9}ll");
+        assert_eq!(engine.eval::<String>("\"h\u{e9}llo\"[2..2]").unwrap(), "");
+        assert_eq!(engine.eval::<char>("\"h\u{e9}llo\"[1]").unwrap(), '\u{e9}');
+    }
+
+    #[test]
+    fn ranges_slice_arrays() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<Vec<i32>>("[1, 2, 3, 4][1..3]").unwrap(), vec!(2, 3));
+        assert_eq!(engine.eval::<Vec<i32>>("[1, 2, 3, 4][1...3]").unwrap(), vec!(2, 3, 4));
+        assert_eq!(engine.eval::<Vec<i32>>("[1, 2, 3][2..2]").unwrap(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn slices_out_of_range_are_diagnosed() {
+        assert_eq!(wrong("\"h\u{e9}llo\"[3..1]"), "index out of range");
+        assert_eq!(wrong("\"h\u{e9}llo\"[0..6]"), "index out of range");
+        assert_eq!(wrong("[1, 2][-1..1]"), "index out of range");
+    }
+
+    #[test]
+    fn concat_joins_strings_and_arrays() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<String>("a = \"h\u{e9}\"\na ++ \"llo\" ++ \"{1}\"").unwrap(), "h\u{e9}llo1");
+        assert_eq!(engine.eval::<Vec<i32>>("a = [1]\nb = a ++ [2, 3] ++ []\na ++ b").unwrap(), vec!(1, 1, 2, 3));
+
+        assert_eq!(wrong("\"a\" ++ 1"), "type mismatch");
+        assert_eq!(wrong("[1] ++ \"a\""), "type mismatch");
+    }
+
+    #[test]
+    fn pow_works_on_ints_and_floats() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval::<i32>("2 ^ 10").unwrap(), 1024);
+        assert_eq!(engine.eval::<i32>("0 ^ 0").unwrap(), 1);
+        assert_eq!(engine.eval::<i32>("(-2) ^ 31").unwrap(), i32::MIN);
+        assert_eq!(engine.eval::<f64>("2 ^ -1").unwrap(), 0.5);
+        assert_eq!(engine.eval::<f64>("2.0 ^ 0.5").unwrap(), 2f64.sqrt());
+        assert_eq!(engine.eval::<f64>("2 ^ 0.5").unwrap(), 2f64.sqrt());
+    }
+
+    #[test]
+    fn pow_overflow_is_diagnosed() {
+        assert_eq!(wrong("2 ^ 40"), "integer overflow");
+        assert_eq!(wrong("2 ^ 31"), "integer overflow");
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("calls = 0\nfunk hit():\n    calls = calls + 1\n    return true").unwrap();
+
+        assert!(!engine.eval::<bool>("false and hit()").unwrap());
+        assert!(engine.eval::<bool>("true or hit()").unwrap());
+        assert_eq!(engine.global::<i32>("calls"), Some(0));
+
+        assert!(engine.eval::<bool>("true and hit()").unwrap());
+        assert!(engine.eval::<bool>("false or hit()").unwrap());
+        assert_eq!(engine.global::<i32>("calls"), Some(2));
+
+        assert!(engine.eval::<bool>("1 and 2").unwrap());
+    }
+
+    #[test]
+    fn records_get_and_set_fields() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("r = { a: 1, b: { c: 2 } }\nalias = r\nr.b.c = 5\nr.a = r.a + 1").unwrap();
+
+        assert_eq!(engine.eval::<i32>("alias.a + alias.b.c").unwrap(), 7);
+        assert_eq!(engine.eval::<String>("\"{r}\"").unwrap(), "{ a: 2, b: { c: 5 } }");
+    }
+
+    #[test]
+    fn unknown_fields_are_diagnosed() {
+        assert_eq!(wrong("{ a: 1 }.b"), "unknown field");
+        assert_eq!(wrong("r = { a: 1 }\nr.b = 2"), "unknown field");
+        assert_eq!(wrong("{ a: 1, a: 2 }"), "field `a` is defined more than once");
+    }
+
+    #[test]
+    fn funks_take_arguments_and_return_values() {
+        let mut engine = Engine::new();
+
+        engine.eval::<()>("funk fib(n):\n    if n < 2:\n        return n\n    return fib(n - 1) + fib(n - 2)\nfunk none(): x = 1").unwrap();
+
+        assert_eq!(engine.eval::<i32>("fib(15)").unwrap(), 610);
+        assert_eq!(engine.eval::<Option<i32>>("none()").unwrap(), None);
+    }
+
+    #[test]
+    fn bad_calls_are_diagnosed() {
+        assert_eq!(wrong("funk f(a): return a\nf()"), "expected 1 arguments, found 0");
+        assert_eq!(wrong("x = 1\nx()"), "can only call funks");
+        assert_eq!(wrong("funk f(n): return f(n + 1)\nf(0)"), "stack overflow");
+    }
+}
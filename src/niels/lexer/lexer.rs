@@ -1,13 +1,14 @@
 use super::*;
 
-use super::super::error::Response::Wrong;
-
 use std::rc::Rc;
 
 pub struct Lexer<'l> {
     tokenizer: Tokenizer<'l>,
-    matchers: Vec<Rc<Matcher<'l>>>,
+    matchers: Vec<Rc<dyn Matcher<'l>>>,
     source: &'l Source,
+
+    // for each string an expression is embedded in, how many `{` the expression left open
+    interpolations: Vec<usize>,
 }
 
 impl<'l> Lexer<'l> {
@@ -16,6 +17,7 @@ impl<'l> Lexer<'l> {
             tokenizer,
             matchers: Vec::new(),
             source,
+            interpolations: Vec::new(),
         }
     }
 
@@ -32,12 +34,23 @@ impl<'l> Lexer<'l> {
 
         lexer
             .matchers
-            .push(Rc::new(KeyMatcher::new(Keyword, &["funk", "pub", "return"])));
+            .push(Rc::new(KeyMatcher::new(
+                Keyword,
+                &[
+                    "funk", "pub", "return", "if", "elif", "else", "while", "for", "in", "break",
+                    "skip", "import", "implement",
+                ],
+            )));
 
         lexer
             .matchers
             .push(Rc::new(KeyMatcher::new(Bool, &["false", "true"])));
 
+        // words, so they aren't picked out of the start of names like `order`
+        lexer
+            .matchers
+            .push(Rc::new(KeyMatcher::new(Operator, &["or", "and", "not"])));
+
         lexer
             .matchers
             .push(Rc::new(ConstantStringMatcher::new(Symbol, &["...", ".."])));
@@ -48,8 +61,7 @@ impl<'l> Lexer<'l> {
         lexer.matchers.push(Rc::new(ConstantStringMatcher::new(
             Operator,
             &[
-                "^", "++", "+", "-", "*", "/", "%", "==", "!=", "<=", ">=", "<", ">", "or", "and",
-                "not",
+                "^", "++", "+", "-", "*", "/", "%", "==", "!=", "<=", ">=", "<", ">",
             ],
         )));
 
@@ -65,7 +77,7 @@ impl<'l> Lexer<'l> {
         lexer
     }
 
-    pub fn match_token(&mut self) -> Result<Option<Token>, ()> {
+    pub fn match_token(&mut self) -> Result<Option<Token>, Diagnostic> {
         for matcher in &mut self.matchers {
             match self.tokenizer.try_match_token(matcher.as_ref())? {
                 Some(t) => return Ok(Some(t)),
@@ -78,18 +90,18 @@ impl<'l> Lexer<'l> {
 }
 
 impl<'l> Iterator for Lexer<'l> {
-    type Item = Result<Token, ()>;
+    type Item = Result<Token, Diagnostic>;
 
-    fn next(&mut self) -> Option<Result<Token, ()>> {
+    fn next(&mut self) -> Option<Result<Token, Diagnostic>> {
         let token = match self.match_token() {
             Ok(hmm) => match hmm {
                 Some(n) => n,
                 None => {
                     let pos = self.tokenizer.pos;
 
-                    return Some(Err(response!(
-                        Wrong("bumped into weird character"),
-                        self.source.file,
+                    let diagnostic = Diagnostic::wrong(
+                        "bumped into weird character",
+                        &self.source.file,
                         Pos(
                             (
                                 pos.0,
@@ -101,13 +113,57 @@ impl<'l> Iterator for Lexer<'l> {
                             ),
                             (pos.1 + 1, pos.1 + 1),
                         )
-                    )));
+                    );
+
+                    // skip past it, so lexing can carry on
+                    self.tokenizer.advance();
+
+                    return Some(Err(diagnostic));
                 }
             },
 
-            Err(_) => return Some(Err(())),
+            Err(diagnostic) => return Some(Err(diagnostic)),
         };
 
+        match (token.token_type.clone(), token.lexeme.as_str()) {
+            (TokenType::Template, _) => self.interpolations.push(0),
+
+            (TokenType::Symbol, "{") => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1
+                }
+            },
+
+            (TokenType::Symbol, "}") => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+
+                    // the string carries on after the expression
+                    self.tokenizer.take_snapshot();
+
+                    let rest = StringRestMatcher.try_match(&mut self.tokenizer);
+
+                    self.tokenizer.commit_snapshot();
+
+                    return match rest {
+                        Ok(Some(token)) => {
+                            if token.token_type == TokenType::Template {
+                                self.interpolations.push(0)
+                            }
+
+                            Some(Ok(token))
+                        },
+                        Ok(None) => None,
+                        Err(diagnostic) => Some(Err(diagnostic)),
+                    };
+                },
+                Some(depth) => *depth -= 1,
+                None => (),
+            },
+
+            _ => (),
+        }
+
         match token.token_type {
             TokenType::EOF => None,
             TokenType::Whitespace => self.next(),
@@ -115,3 +171,45 @@ impl<'l> Iterator for Lexer<'l> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::niels::engine::Engine;
+    use crate::niels::error::Response;
+
+    fn eval(code: &str) -> String {
+        Engine::new().eval::<String>(code).unwrap()
+    }
+
+    fn wrong(code: &str) -> String {
+        match Engine::new().eval::<()>(code).unwrap_err()[0].response {
+            Response::Wrong(ref message) => message.clone(),
+            ref other => panic!("expected an error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn interpolates_expressions() {
+        assert_eq!(eval("name = \"ada\"\nage = 36\n\"hello {name}, you are {age + 1}\""), "hello ada, you are 37");
+        assert_eq!(eval("\"{1}{2.5}{'c'}{true}\""), "12.5ctrue");
+        assert_eq!(eval("\"{[1, 2]}\""), "[1, 2]");
+    }
+
+    #[test]
+    fn braces_nest_inside_interpolations() {
+        assert_eq!(eval("\"x is {{x: 1}.x}\""), "x is 1");
+        assert_eq!(eval("n = 2\n\"a {\"b {n} c\"} d\""), "a b 2 c d");
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        assert_eq!(eval("\"\\{not} {1}\""), "{not} 1");
+        assert_eq!(eval("\"{1} \\}\""), "1 }");
+    }
+
+    #[test]
+    fn unterminated_templates_are_diagnosed() {
+        assert_eq!(wrong("x = 1\ny = \"a {x}"), "unterminated delimeter `\"`");
+        assert_eq!(wrong("x = 1\ny = \"a {x\""), "unterminated delimeter `\"`");
+    }
+}
@@ -1,4 +1,3 @@
-use super::super::error::Response::*;
 use super::*;
 
 macro_rules! token {
@@ -38,14 +37,14 @@ macro_rules! token {
 }
 
 pub trait Matcher<'t> {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()>;
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic>;
 }
 
 pub struct CommentMatcher;
 
 impl<'t> Matcher<'t> for CommentMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
-        if tokenizer.peek_range(1).unwrap_or_else(String::new) == "#" {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
+        if tokenizer.peek_range(1).unwrap_or_default() == "#" {
             while !tokenizer.end() && tokenizer.peek() != Some('\n') {
                 tokenizer.advance()
             }
@@ -72,7 +71,7 @@ impl ConstantStringMatcher {
 }
 
 impl<'t> Matcher<'t> for ConstantStringMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         for constant in self.constants {
             let len = constant.len();
             let c = match tokenizer.peek_range(len) {
@@ -113,7 +112,7 @@ impl ConstantCharMatcher {
 }
 
 impl<'t> Matcher<'t> for ConstantCharMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         let c = tokenizer.peek().unwrap();
 
         for constant in self.constants {
@@ -137,7 +136,7 @@ impl<'t> Matcher<'t> for ConstantCharMatcher {
 pub struct StringLiteralMatcher;
 
 impl<'t> Matcher<'t> for StringLiteralMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         let mut raw_marker = false;
 
         let mut pos = tokenizer.pos;
@@ -154,9 +153,9 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
 
                     '"'
                 } else if tokenizer.peek_n(1) == Some('\'') {
-                    return Err(response!(
-                        Wrong("no such thing as a raw character literal"),
-                        tokenizer.source.file,
+                    return Err(Diagnostic::wrong(
+                        "no such thing as a raw character literal",
+                        &tokenizer.source.file,
                         Pos(
                             (
                                 pos.0,
@@ -184,9 +183,9 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
 
         loop {
             if tokenizer.end() {
-                return Err(response!(
-                    Wrong(format!("unterminated delimeter `{}`", delimeter)),
-                    tokenizer.source.file,
+                return Err(Diagnostic::wrong(
+                    format!("unterminated delimeter `{}`", delimeter),
+                    &tokenizer.source.file,
                     Pos(
                         (
                             pos.0 + 1,
@@ -209,15 +208,14 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
 
                 string.push(tokenizer.next().unwrap())
             } else if found_escape {
-                string.push(match tokenizer.next().unwrap() {
-                    c @ '\\' | c @ '\'' | c @ '"' => c,
-                    'n' => '\n',
-                    'r' => '\r',
-                    't' => '\t',
-                    escaped => {
-                        return Err(response!(
-                            Wrong(format!("unexpected escape character: {}", escaped)),
-                            tokenizer.source.file,
+                let escaped = tokenizer.next().unwrap();
+
+                string.push(match unescape(escaped) {
+                    Some(c) => c,
+                    None => {
+                        return Err(Diagnostic::wrong(
+                            format!("unexpected escape character: {}", escaped),
+                            &tokenizer.source.file,
                             Pos(
                                 (
                                     tokenizer.pos.0,
@@ -242,10 +240,17 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
                         found_escape = true
                     }
 
+                    // an embedded expression, the lexer picks the string back up after its `}`
+                    '{' if delimeter == '"' => {
+                        tokenizer.advance();
+
+                        return Ok(Some(token!(tokenizer, Template, string)));
+                    }
+
                     // check for valid closing delimeter and alternative
                     c => {
                         if c == delimeter {
-                            if string.len() > 0 && string != " " {
+                            if !string.is_empty() && string != " " {
                                 break;
                             } else {
                                 string.push(tokenizer.next().unwrap())
@@ -272,9 +277,9 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
             if string.len() > 1 {
                 let pos = tokenizer.last_position();
 
-                Err(response!(
-                    Wrong("char literals may not contain more than one codepoint"),
-                    tokenizer.source.file,
+                Err(Diagnostic::wrong(
+                    "char literals may not contain more than one codepoint",
+                    &tokenizer.source.file,
                     Pos(
                         (
                             pos.0,
@@ -298,10 +303,70 @@ impl<'t> Matcher<'t> for StringLiteralMatcher {
     }
 }
 
+// the rest of an interpolated string, from the `}` closing an embedded expression up to the
+// next `{` or the closing `"`
+pub struct StringRestMatcher;
+
+impl<'t> Matcher<'t> for StringRestMatcher {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
+        let mut string = String::new();
+
+        loop {
+            let pos = tokenizer.pos;
+
+            let wrong = |tokenizer: &Tokenizer<'t>, message: String| {
+                Diagnostic::wrong(
+                    message,
+                    &tokenizer.source.file,
+                    Pos(
+                        (
+                            pos.0,
+                            tokenizer
+                                .source
+                                .lines
+                                .get(pos.0.saturating_sub(1))
+                                .unwrap_or(tokenizer.source.lines.last().unwrap())
+                                .to_string()
+                        ),
+                        (pos.1, pos.1 + 1),
+                    )
+                )
+            };
+
+            match tokenizer.next() {
+                Some('"') => return Ok(Some(token!(tokenizer, Str, string))),
+                Some('{') => return Ok(Some(token!(tokenizer, Template, string))),
+
+                Some('\\') => match tokenizer.next() {
+                    Some(escaped) => match unescape(escaped) {
+                        Some(c) => string.push(c),
+                        None => return Err(wrong(tokenizer, format!("unexpected escape character: {}", escaped))),
+                    },
+                    None => return Err(wrong(tokenizer, String::from("unterminated delimeter `\"`"))),
+                },
+
+                Some(c) => string.push(c),
+                None => return Err(wrong(tokenizer, String::from("unterminated delimeter `\"`"))),
+            }
+        }
+    }
+}
+
+// what an escape like `\n` stands for
+fn unescape(c: char) -> Option<char> {
+    match c {
+        '\\' | '\'' | '"' | '{' | '}' => Some(c),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        _ => None,
+    }
+}
+
 pub struct IdentifierMatcher;
 
 impl<'t> Matcher<'t> for IdentifierMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         let peeked = tokenizer.peek().unwrap();
 
         if !peeked.is_alphabetic() && peeked != '_' {
@@ -321,11 +386,11 @@ impl<'t> Matcher<'t> for IdentifierMatcher {
 pub struct NumberLiteralMatcher;
 
 impl<'t> Matcher<'t> for NumberLiteralMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         let mut accum = String::new();
 
         let curr = tokenizer.next().unwrap();
-        if curr.is_digit(10) {
+        if curr.is_ascii_digit() {
             accum.push(curr)
         } else if curr == '.' {
             accum.push_str("0.")
@@ -343,13 +408,18 @@ impl<'t> Matcher<'t> for NumberLiteralMatcher {
                 current = tokenizer.peek().unwrap()
             }
 
-            if !current.is_whitespace() && current.is_digit(10) || current == '.' {
+            if !current.is_whitespace() && current.is_ascii_digit() || current == '.' {
+                // `..` and `...` after a number start a range
+                if current == '.' && tokenizer.peek_n(1) == Some('.') {
+                    break;
+                }
+
                 if current == '.' && accum.contains('.') {
                     let pos = tokenizer.pos;
 
-                    return Err(response!(
-                        Wrong("unexpected extra decimal point"),
-                        tokenizer.source.file,
+                    return Err(Diagnostic::wrong(
+                        "unexpected extra decimal point",
+                        &tokenizer.source.file,
                         Pos(
                             (
                                 pos.0,
@@ -407,7 +477,7 @@ impl KeyMatcher {
 }
 
 impl<'t> Matcher<'t> for KeyMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         for constant in self.constants {
             if let Some(s) = tokenizer.peek_range(constant.len()) {
                 if s == *constant {
@@ -434,7 +504,7 @@ impl<'t> Matcher<'t> for KeyMatcher {
 pub struct EOLMatcher;
 
 impl<'t> Matcher<'t> for EOLMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         if tokenizer.peek() == Some('\n') {
             tokenizer.pos.0 += 1;
             tokenizer.pos.1 = 0;
@@ -450,7 +520,7 @@ impl<'t> Matcher<'t> for EOLMatcher {
 pub struct WhitespaceMatcher;
 
 impl<'t> Matcher<'t> for WhitespaceMatcher {
-    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, ()> {
+    fn try_match(&self, tokenizer: &mut Tokenizer<'t>) -> Result<Option<Token>, Diagnostic> {
         let string = tokenizer.collect_while(|c| c.is_whitespace() && c != '\n');
 
         if !string.is_empty() {
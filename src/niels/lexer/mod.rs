@@ -1,8 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod lexer;
 pub mod matcher;
 pub mod token;
 pub mod tokenizer;
 
+use super::error::Diagnostic;
 use super::source::Source;
 
 pub use self::lexer::*;
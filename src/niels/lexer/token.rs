@@ -1,6 +1,7 @@
 use colored::Colorize;
 use std::fmt;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     Identifier,
@@ -8,6 +9,7 @@ pub enum TokenType {
     Float,
     Keyword,
     Str,
+    Template, // the text of a string up to an embedded expression
     Char,
     Symbol,
     Operator,
@@ -26,6 +28,7 @@ impl fmt::Display for TokenType {
             Int => write!(f, "int"),
             Float => write!(f, "float"),
             Str => write!(f, "str"),
+            Template => write!(f, "interpolated str"),
             Char => write!(f, "char"),
             Keyword => write!(f, "keyword"),
             Bool => write!(f, "bool"),
@@ -51,7 +54,13 @@ impl fmt::Display for Pos {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let linepad = format!("{:5} │", " ").blue().bold();
         let lineno = format!("{:5} │ ", (self.0).0).blue().bold();
-        let mut mark = (self.0).1[(self.1).0.saturating_sub(1)..(self.1).1].to_string();
+
+        // positions at the end of a line point just past it
+        let line = &(self.0).1;
+        let end = (self.1).1.min(line.len());
+        let start = (self.1).0.saturating_sub(1).min(end);
+
+        let mut mark = line[start..end].to_string();
 
         if mark.split_whitespace().count() == 0 {
             mark = format!("{:─>count$}", ">".red().bold(), count = mark.len());
@@ -61,7 +70,7 @@ impl fmt::Display for Pos {
 
         let mut arrows = format!("{: <count$}", " ", count = (self.1).0);
 
-        for _ in 0..(self.1).1 - (self.1).0 + 1 {
+        for _ in 0..(self.1).1.saturating_sub((self.1).0) + 1 {
             arrows.push('^')
         }
 
@@ -70,9 +79,9 @@ impl fmt::Display for Pos {
             "\n{}\n{}{}{}{}\n{}{}",
             linepad,
             lineno,
-            &(self.0).1[..(self.1).0.saturating_sub(1)],
+            &line[..start],
             mark,
-            &(self.0).1[(self.1).1..],
+            &line[end..],
             linepad,
             arrows.red().bold()
         )
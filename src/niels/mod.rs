@@ -1,7 +1,8 @@
-#[macro_use]
 pub mod error;
 pub mod source;
 
 pub mod lexer;
 pub mod parser;
-pub mod interpreter;
\ No newline at end of file
+pub mod interpreter;
+
+pub mod engine;
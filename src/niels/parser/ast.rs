@@ -1,6 +1,5 @@
 use std::fmt;
 use std::rc::Rc;
-use std::collections::HashMap;
 
 use super::*;
 
@@ -9,9 +8,12 @@ pub enum StatementNode {
     Expression(Expression),
     Assignment(Expression, Expression),
     Return(Option<Rc<Expression>>),
-    Implement(Expression, Expression, Option<Expression>),
+    Implement(String, Option<String>, Vec<Statement>), // the type, the trait, and the funks
     Import(String, Vec<String>),
     Function(String, Vec<String>, Vec<Statement>),
+    If(Expression, Vec<Statement>, Vec<(Expression, Vec<Statement>)>, Option<Vec<Statement>>),
+    While(Expression, Vec<Statement>),
+    For(String, Expression, Vec<Statement>),
     Public(Rc<Statement>),
     Skip,
     Break,
@@ -29,9 +31,10 @@ impl Statement {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionNode {
-    Int(u64),
+    Int(i64),
     Float(f64),
     Str(String),
     Char(char),
@@ -43,10 +46,13 @@ pub enum ExpressionNode {
     Identifier(String),
     Binary(Rc<Expression>, Operator, Rc<Expression>),
     Array(Vec<Expression>),
-    Record(HashMap<String, Expression>),
+    Record(Option<String>, Vec<(String, Expression)>), // `Point { x: 1 }` is of type `Point`
     Index(Rc<Expression>, Rc<Expression>, bool), // whether_index_is_an_array_index: bool
+    Range(Rc<Expression>, Rc<Expression>, bool), // inclusive: bool
 
     Call(Rc<Expression>, Vec<Expression>),
+    Lambda(Vec<String>, Rc<Expression>), // `|a, b| a + b`
+    Format(Vec<Expression>),             // `"a {b}"`, its parts displayed and joined
 
     Empty,
     EOF,
@@ -84,7 +90,7 @@ pub enum Operator {
 }
 
 impl Operator {
-    pub fn from_str(operator: &str) -> Option<(Operator, u8)> {
+    pub fn from_lexeme(operator: &str) -> Option<(Operator, u8)> {
         use self::Operator::*;
 
         let op_prec = match operator {
@@ -1,4 +1,5 @@
 pub mod ast;
+#[allow(clippy::module_inception)]
 pub mod parser;
 
 use super::error::*;
@@ -7,3 +8,25 @@ use super::source::*;
 
 pub use self::ast::*;
 pub use self::parser::*;
+
+pub fn parse(source: &Source) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let content = source.lines.join("\n");
+    let lexer = Lexer::default(content.chars().collect(), source);
+
+    let mut tokens = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    for token_result in lexer {
+        match token_result {
+            Ok(token) => tokens.push(token),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+
+        if diagnostics.len() == DIAGNOSTIC_LIMIT {
+            break;
+        }
+    }
+
+    // the lexer skips what it can't make sense of, so the parser can still report its own
+    Parser::new(tokens, source).with_diagnostics(diagnostics).parse()
+}
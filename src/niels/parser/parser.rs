@@ -1,8 +1,8 @@
 use super::*;
-use Response::Wrong;
+use Response::Note;
 
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::mem;
 
 pub struct Parser<'p> {
     index: usize,
@@ -11,6 +11,8 @@ pub struct Parser<'p> {
 
     indent_standard: usize,
     indent:          usize,
+
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'p> Parser<'p> {
@@ -22,20 +24,81 @@ impl<'p> Parser<'p> {
 
             indent_standard: 0,
             indent: 0,
+
+            diagnostics: Vec::new(),
+        }
+    }
+
+    // starts out with the lexer's diagnostics, which count towards the same limit
+    pub fn with_diagnostics(mut self, diagnostics: Vec<Diagnostic>) -> Self {
+        self.diagnostics = diagnostics;
+
+        if self.gave_up() {
+            self.give_up()
         }
+
+        self
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Statement>, ()> {
+    pub fn parse(&mut self) -> Result<Vec<Statement>, Vec<Diagnostic>> {
         let mut ast = Vec::new();
 
-        while self.remaining() > 0 {
-            ast.push(self.parse_statement()?)
+        while self.remaining() > 0 && !self.gave_up() {
+            match self.parse_statement() {
+                Ok(statement) => ast.push(statement),
+                Err(diagnostic) => self.recover(diagnostic),
+            }
         }
 
-        Ok(ast)
+        if self.diagnostics.is_empty() {
+            Ok(ast)
+        } else {
+            Err(mem::take(&mut self.diagnostics))
+        }
+    }
+
+    fn recover(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+
+        if self.gave_up() {
+            self.give_up()
+        } else {
+            self.synchronize()
+        }
+    }
+
+    fn give_up(&mut self) {
+        self.diagnostics.push(Diagnostic::new(
+            Note(format!("stopped after {} errors", DIAGNOSTIC_LIMIT)),
+            &self.source.file,
+            None,
+        ))
+    }
+
+    // skips the rest of a broken statement: its own line, and any lines nested below it
+    fn synchronize(&mut self) {
+        self.skip_line();
+
+        while self.remaining() > 0 && self.get_indent() > self.indent {
+            self.skip_line()
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while self.remaining() > 0 && self.current_type() != TokenType::EOL {
+            self.index += 1
+        }
+
+        while self.remaining() > 0 && self.current_type() == TokenType::EOL {
+            self.index += 1
+        }
+    }
+
+    fn gave_up(&self) -> bool {
+        self.diagnostics.len() >= DIAGNOSTIC_LIMIT
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, ()> {
+    fn parse_statement(&mut self) -> Result<Statement, Diagnostic> {
         use self::TokenType::*;
 
         while self.current_type() == EOL && self.remaining() != 0 {
@@ -142,15 +205,7 @@ impl<'p> Parser<'p> {
                         Vec::new()
                     };
 
-                    self.eat_lexeme(":")?;
-
-                    let body = if self.current_lexeme() == "\n" {
-                        self.next()?;
-
-                        self.parse_body()?
-                    } else {
-                        vec!(self.parse_statement()?)
-                    };
+                    let body = self.parse_block()?;
 
                     return Ok(
                         Statement::new(
@@ -164,6 +219,145 @@ impl<'p> Parser<'p> {
                     )
                 },
 
+                "if" => {
+                    let indent = self.get_indent();
+
+                    self.next()?;
+
+                    let condition = self.parse_expression()?;
+                    let body = self.parse_block()?;
+
+                    let mut elifs = Vec::new();
+                    let mut else_body = None;
+
+                    while let Some(keyword) = self.peek_branch(indent)? {
+                        self.next()?;
+
+                        if keyword == "elif" {
+                            let condition = self.parse_expression()?;
+
+                            elifs.push((condition, self.parse_block()?))
+                        } else {
+                            else_body = Some(self.parse_block()?);
+
+                            break;
+                        }
+                    }
+
+                    return Ok(
+                        Statement::new(
+                            StatementNode::If(
+                                condition,
+                                body,
+                                elifs,
+                                else_body,
+                            ),
+                            position,
+                        )
+                    )
+                },
+
+                "while" => {
+                    self.next()?;
+
+                    let condition = self.parse_expression()?;
+                    let body = self.parse_block()?;
+
+                    return Ok(
+                        Statement::new(
+                            StatementNode::While(condition, body),
+                            position,
+                        )
+                    )
+                },
+
+                "for" => {
+                    self.next()?;
+
+                    let name = self.eat_type(&TokenType::Identifier)?;
+
+                    self.eat_lexeme("in")?;
+
+                    let iterable = self.parse_expression()?;
+                    let body = self.parse_block()?;
+
+                    return Ok(
+                        Statement::new(
+                            StatementNode::For(name, iterable, body),
+                            position,
+                        )
+                    )
+                },
+
+                // `import foo.bar`, or `import foo.bar: a, b` for some of its items
+                "import" => {
+                    self.next()?;
+
+                    let mut path = self.eat_type(&Identifier)?;
+
+                    while self.current_lexeme() == "." {
+                        self.next()?;
+
+                        path.push('.');
+                        path.push_str(&self.eat_type(&Identifier)?)
+                    }
+
+                    let mut names = Vec::new();
+
+                    if self.current_lexeme() == ":" {
+                        self.next()?;
+
+                        names.push(self.eat_type(&Identifier)?);
+
+                        while self.current_lexeme() == "," {
+                            self.next()?;
+
+                            names.push(self.eat_type(&Identifier)?)
+                        }
+                    }
+
+                    Statement::new(
+                        StatementNode::Import(path, names),
+                        self.span_from(position)
+                    )
+                },
+
+                // `implement Type:`, or `implement Trait for Type:`
+                "implement" => {
+                    self.next()?;
+
+                    let name = self.eat_type(&Identifier)?;
+
+                    let (name, trait_name) = if self.current_lexeme() == "for" {
+                        self.next()?;
+
+                        (self.eat_type(&Identifier)?, Some(name))
+                    } else {
+                        (name, None)
+                    };
+
+                    let body = self.parse_block()?;
+
+                    return Ok(
+                        Statement::new(
+                            StatementNode::Implement(name, trait_name, body),
+                            position,
+                        )
+                    )
+                },
+
+                "break" => {
+                    self.next()?;
+
+                    Statement::new(StatementNode::Break, position)
+                },
+
+                "skip" => {
+                    self.next()?;
+
+                    Statement::new(StatementNode::Skip, position)
+                },
+
                 _ => {
                     let expression = self.parse_expression()?;
 
@@ -202,32 +396,36 @@ impl<'p> Parser<'p> {
         Ok(statement)
     }
 
-    fn parse_body(&mut self) -> Result<Vec<Statement>, ()> {
+    fn parse_body(&mut self) -> Result<Vec<Statement>, Diagnostic> {
         let backup_indent = self.indent;
         self.indent       = self.get_indent();
 
         if self.indent_standard == 0 {
             self.indent_standard = self.indent
-        } else {
-            if self.indent % self.indent_standard != 0 {
-                return Err(
-                    response!(
-                        Wrong(format!("found inconsistently indented token")),
-                        self.source.file,
-                        self.current_position()
-                    )
+        } else if !self.indent.is_multiple_of(self.indent_standard) {
+            self.indent = backup_indent;
+
+            return Err(
+                Diagnostic::wrong(
+                    "found inconsistently indented token",
+                    &self.source.file,
+                    self.current_position()
                 )
-            }
+            )
         }
 
         let mut stack = Vec::new();
 
-        while !self.is_dedent() && self.remaining() > 0 {
-            let statement = self.parse_statement()?;
+        while !self.is_dedent() && self.remaining() > 0 && !self.gave_up() {
+            match self.parse_statement() {
+                Ok(statement) => {
+                    self.next_newline()?;
 
-            self.next_newline()?;
+                    stack.push(statement)
+                },
 
-            stack.push(statement)
+                Err(diagnostic) => self.recover(diagnostic),
+            }
         }
 
         self.indent = backup_indent;
@@ -235,7 +433,39 @@ impl<'p> Parser<'p> {
         Ok(stack)
     }
 
-    fn try_parse_compound(&mut self, left: &Expression) -> Result<Option<Statement>, ()> {
+    // `: statement` on the same line, or an indented body on the following lines
+    fn parse_block(&mut self) -> Result<Vec<Statement>, Diagnostic> {
+        self.eat_lexeme(":")?;
+
+        if self.current_lexeme() == "\n" {
+            self.next()?;
+
+            self.parse_body()
+        } else {
+            Ok(vec!(self.parse_statement()?))
+        }
+    }
+
+    // finds an `elif` or `else` continuing the `if` at `indent`, leaving the parser on it
+    fn peek_branch(&mut self, indent: usize) -> Result<Option<String>, Diagnostic> {
+        let backup_index = self.index;
+
+        self.next_newline()?;
+
+        if self.remaining() > 0
+            && self.current_type() == TokenType::Keyword
+            && ["elif", "else"].contains(&self.current_lexeme().as_str())
+            && self.get_indent() == indent
+        {
+            Ok(Some(self.current_lexeme()))
+        } else {
+            self.index = backup_index;
+
+            Ok(None)
+        }
+    }
+
+    fn try_parse_compound(&mut self, left: &Expression) -> Result<Option<Statement>, Diagnostic> {
         if self.current_type() != TokenType::Operator {
             return Ok(None);
         }
@@ -247,7 +477,7 @@ impl<'p> Parser<'p> {
         let mut result = None;
 
         if self::Operator::is_compoundable(&c) {
-            let op = self::Operator::from_str(&c).unwrap().0;
+            let op = self::Operator::from_lexeme(&c).unwrap().0;
 
             let position = self.current_position();
 
@@ -275,7 +505,25 @@ impl<'p> Parser<'p> {
         Ok(result)
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, ()> {
+    fn parse_expression(&mut self) -> Result<Expression, Diagnostic> {
+        let start = self.parse_operation()?;
+
+        if self.current_type() == TokenType::Symbol && ["..", "..."].contains(&self.current_lexeme().as_str()) {
+            let inclusive = self.eat()? == "...";
+            let end = self.parse_operation()?;
+
+            let position = start.pos.clone();
+
+            Ok(Expression::new(
+                ExpressionNode::Range(Rc::new(start), Rc::new(end), inclusive),
+                self.span_from(position),
+            ))
+        } else {
+            Ok(start)
+        }
+    }
+
+    fn parse_operation(&mut self) -> Result<Expression, Diagnostic> {
         let atom = self.parse_atom()?;
 
         if self.current_type() == TokenType::Operator {
@@ -285,7 +533,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn parse_atom(&mut self) -> Result<Expression, ()> {
+    fn parse_atom(&mut self) -> Result<Expression, Diagnostic> {
         use self::TokenType::*;
 
         if self.remaining() == 0 {
@@ -298,10 +546,20 @@ impl<'p> Parser<'p> {
             let position = self.current_position();
 
             let expression = match token_type {
-                Int => Expression::new(
-                    ExpressionNode::Int(self.eat()?.parse::<u64>().unwrap()),
-                    position,
-                ),
+                Int => {
+                    // the lexer folds a leading `-` into the literal, so the sign counts towards
+                    // the range like it does for `-2147483648`
+                    match self.eat()?.parse::<i64>() {
+                        Ok(int) => Expression::new(ExpressionNode::Int(int), position),
+                        Err(_) => {
+                            return Err(Diagnostic::wrong(
+                                "int literal is too large",
+                                &self.source.file,
+                                position
+                            ))
+                        },
+                    }
+                },
 
                 Float => Expression::new(
                     ExpressionNode::Float(self.eat()?.parse::<f64>().unwrap()),
@@ -315,7 +573,63 @@ impl<'p> Parser<'p> {
 
                 Str => Expression::new(ExpressionNode::Str(self.eat()?), position),
 
-                Identifier => Expression::new(ExpressionNode::Identifier(self.eat()?), position),
+                // the lexer splits `"a {b} c"` into `"a {`, `b`, `} c"`
+                Template => {
+                    let mut parts = Vec::new();
+                    let mut text = self.eat()?;
+
+                    loop {
+                        if !text.is_empty() {
+                            parts.push(Expression::new(ExpressionNode::Str(text), position.clone()))
+                        }
+
+                        parts.push(self.parse_expression()?);
+
+                        if self.remaining() == 0 {
+                            return Err(Diagnostic::wrong(
+                                "expected `}` closing the embedded expression",
+                                &self.source.file,
+                                self.current_position()
+                            ));
+                        }
+
+                        match self.current_type() {
+                            Template => text = self.eat()?,
+
+                            Str => {
+                                let text = self.eat()?;
+
+                                if !text.is_empty() {
+                                    parts.push(Expression::new(ExpressionNode::Str(text), position.clone()))
+                                }
+
+                                break;
+                            },
+
+                            _ => {
+                                return Err(Diagnostic::wrong(
+                                    format!("expected `}}` closing the embedded expression, found `{}`", self.current_lexeme()),
+                                    &self.source.file,
+                                    self.current_position()
+                                ))
+                            },
+                        }
+                    }
+
+                    Expression::new(ExpressionNode::Format(parts), self.span_from(position))
+                },
+
+                Identifier => {
+                    let name = self.eat()?;
+
+                    if self.remaining() > 0 && self.current_lexeme() == "{" {
+                        let content = self.parse_block_of(("{", "}"), &Self::_parse_definition_comma)?;
+
+                        Expression::new(ExpressionNode::Record(Some(name), content), self.span_from(position))
+                    } else {
+                        Expression::new(ExpressionNode::Identifier(name), position)
+                    }
+                },
 
                 Bool => Expression::new(ExpressionNode::Bool(self.eat()? == "true"), position),
 
@@ -345,9 +659,9 @@ impl<'p> Parser<'p> {
                     },
 
                     ref symbol => return Err(
-                        response!(
-                            Wrong(format!("unexpected operator `{}`", symbol)),
-                            self.source.file,
+                        Diagnostic::wrong(
+                            format!("unexpected operator `{}`", symbol),
+                            &self.source.file,
                             self.current_position()
                         )
                     )
@@ -355,16 +669,24 @@ impl<'p> Parser<'p> {
 
                 Symbol => match self.current_lexeme().as_str() {
                     "{" => {
-                        let body = self.parse_block_of(("{", "}"), &Self::_parse_definition_comma)?;
-
-                        let content: HashMap<_, _> = body.into_iter().collect();
+                        let content = self.parse_block_of(("{", "}"), &Self::_parse_definition_comma)?;
 
                         Expression::new(
-                            ExpressionNode::Record(content),
+                            ExpressionNode::Record(None, content),
                             position
                         )
                     },
 
+                    "|" => {
+                        let params = self.parse_block_of(("|", "|"), &Self::_parse_name_comma)?;
+                        let body = self.parse_expression()?;
+
+                        Expression::new(
+                            ExpressionNode::Lambda(params, Rc::new(body)),
+                            self.span_from(position)
+                        )
+                    },
+
                     "[" => Expression::new(
                         ExpressionNode::Array(self.parse_block_of(("[", "]"), &Self::_parse_expression_comma)?),
                         self.span_from(position)
@@ -391,18 +713,18 @@ impl<'p> Parser<'p> {
                     },
 
                     ref symbol => return Err(
-                        response!(
-                            Wrong(format!("unexpected symbol `{}`", symbol)),
-                            self.source.file,
+                        Diagnostic::wrong(
+                            format!("unexpected symbol `{}`", symbol),
+                            &self.source.file,
                             self.current_position()
                         )
                     )
                 },
 
                 ref token_type => {
-                    return Err(response!(
-                        Wrong(format!("unexpected token `{}`", token_type)),
-                        self.source.file,
+                    return Err(Diagnostic::wrong(
+                        format!("unexpected token `{}`", token_type),
+                        &self.source.file,
                         self.current_position()
                     ))
                 }
@@ -416,7 +738,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn parse_postfix(&mut self, expression: Expression) -> Result<Expression, ()> {
+    fn parse_postfix(&mut self, expression: Expression) -> Result<Expression, Diagnostic> {
         if self.remaining() == 0 {
             return Ok(expression);
         }
@@ -476,18 +798,18 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn parse_binary(&mut self, left: Expression) -> Result<Expression, ()> {
+    fn parse_binary(&mut self, left: Expression) -> Result<Expression, Diagnostic> {
         let left_position = left.pos.clone();
 
         let mut expression_stack = vec![left];
-        let mut operator_stack = vec![Operator::from_str(&self.eat()?).unwrap()];
+        let mut operator_stack = vec![Operator::from_lexeme(&self.eat()?).unwrap()];
 
         expression_stack.push(self.parse_atom()?);
 
-        while operator_stack.len() > 0 {
+        while !operator_stack.is_empty() {
             while self.current_type() == TokenType::Operator {
                 let position = self.current_position();
-                let (operator, precedence) = Operator::from_str(&self.eat()?).unwrap();
+                let (operator, precedence) = Operator::from_lexeme(&self.eat()?).unwrap();
 
                 if precedence < operator_stack.last().unwrap().1 {
                     let right = expression_stack.pop().unwrap();
@@ -506,11 +828,11 @@ impl<'p> Parser<'p> {
                         expression_stack.push(self.parse_atom()?);
                         operator_stack.push((operator, precedence))
                     } else {
-                        return Err(response!(
-                            Wrong("reached EOF in operation"),
-                            self.source.file,
-                            position
-                        ));
+                        return Err(Diagnostic::wrong(
+                            "reached EOF in operation",
+                            &self.source.file,
+                            position)
+                        );
                     }
                 } else {
                     expression_stack.push(self.parse_atom()?);
@@ -542,8 +864,11 @@ impl<'p> Parser<'p> {
     fn parse_block_of<B>(
         &mut self,
         delimeters: (&str, &str),
-        parse_with: &Fn(&mut Self) -> Result<Option<B>, ()>,
-    ) -> Result<Vec<B>, ()> {
+        parse_with: &dyn Fn(&mut Self) -> Result<Option<B>, Diagnostic>,
+    ) -> Result<Vec<B>, Diagnostic> {
+        let open_index = self.index;
+        let open_position = self.current_position();
+
         self.eat_lexeme(delimeters.0)?;
 
         if self.current_lexeme() == delimeters.1 {
@@ -556,6 +881,17 @@ impl<'p> Parser<'p> {
         let mut nest_count = 1;
 
         while nest_count > 0 {
+            if self.remaining() == 0 {
+                // back to the opening line, so recovery doesn't lose the rest of the file
+                self.index = open_index;
+
+                return Err(Diagnostic::wrong(
+                    format!("unclosed delimeter `{}`", delimeters.0),
+                    &self.source.file,
+                    open_position
+                ));
+            }
+
             if self.current_lexeme() == delimeters.1 && self.current_type() == TokenType::Symbol {
                 nest_count -= 1
             } else if self.current_lexeme() == delimeters.0
@@ -589,7 +925,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn _parse_definition_comma(self: &mut Self) -> Result<Option<(String, Expression)>, ()> {
+    fn _parse_definition_comma(&mut self) -> Result<Option<(String, Expression)>, Diagnostic> {
         if self.remaining() > 0 && self.current_lexeme() == "\n" {
             self.next()?
         }
@@ -613,9 +949,9 @@ impl<'p> Parser<'p> {
         if self.remaining() > 0 {
             if ![",", "\n"].contains(&self.current_lexeme().as_str()) {
                 return Err(
-                    response!(
-                        Wrong(format!("expected `,` or newline, found `{}`", self.current_lexeme())),
-                        self.source.file,
+                    Diagnostic::wrong(
+                        format!("expected `,` or newline, found `{}`", self.current_lexeme()),
+                        &self.source.file,
                         self.current_position()
                     )
                 )
@@ -631,7 +967,7 @@ impl<'p> Parser<'p> {
         Ok(param)
     }
 
-    fn _parse_name_comma(self: &mut Self) -> Result<Option<String>, ()> {
+    fn _parse_name_comma(&mut self) -> Result<Option<String>, Diagnostic> {
         if self.remaining() == 0 {
             Ok(None)
         } else {
@@ -644,9 +980,9 @@ impl<'p> Parser<'p> {
             if self.remaining() > 0 {
                 if ![",", "\n"].contains(&self.current_lexeme().as_str()) {
                     return Err(
-                        response!(
-                            Wrong(format!("expected `,` or newline, found `{}`", self.current_lexeme())),
-                            self.source.file,
+                        Diagnostic::wrong(
+                            format!("expected `,` or newline, found `{}`", self.current_lexeme()),
+                            &self.source.file,
                             self.current_position()
                         )
                     )
@@ -663,7 +999,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn _parse_expression(self: &mut Self) -> Result<Option<Expression>, ()> {
+    fn _parse_expression(&mut self) -> Result<Option<Expression>, Diagnostic> {
         let expression = self.parse_expression()?;
 
         match expression.node {
@@ -672,7 +1008,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn _parse_expression_comma(self: &mut Self) -> Result<Option<Expression>, ()> {
+    fn _parse_expression_comma(&mut self) -> Result<Option<Expression>, Diagnostic> {
         if self.remaining() > 0 && self.current_lexeme() == "\n" {
             self.next()?
         }
@@ -694,16 +1030,16 @@ impl<'p> Parser<'p> {
         expression
     }
 
-    fn new_line(&mut self) -> Result<(), ()> {
+    fn new_line(&mut self) -> Result<(), Diagnostic> {
         if self.remaining() > 0 {
             match self.current_lexeme().as_str() {
                 "\n" => self.next(),
-                _ => Err(response!(
-                    Wrong(format!(
+                _ => Err(Diagnostic::wrong(
+                    format!(
                         "expected new line found: `{}`",
                         self.current_lexeme()
-                    )),
-                    self.source.file,
+                    ),
+                    &self.source.file,
                     self.current_position()
                 )),
             }
@@ -712,7 +1048,7 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn next_newline(&mut self) -> Result<(), ()> {
+    fn next_newline(&mut self) -> Result<(), Diagnostic> {
         while self.current_lexeme() == "\n" && self.remaining() > 0 {
             self.next()?
         }
@@ -728,14 +1064,14 @@ impl<'p> Parser<'p> {
         self.get_indent() < self.indent && self.current_lexeme() != "\n"
     }
 
-    fn next(&mut self) -> Result<(), ()> {
+    fn next(&mut self) -> Result<(), Diagnostic> {
         if self.index <= self.tokens.len() {
             self.index += 1;
             Ok(())
         } else {
-            Err(response!(
-                Wrong("moving outside token stack"),
-                self.source.file,
+            Err(Diagnostic::wrong(
+                "moving outside token stack",
+                &self.source.file,
                 self.current_position()
             ))
         }
@@ -776,46 +1112,46 @@ impl<'p> Parser<'p> {
         }
     }
 
-    fn eat(&mut self) -> Result<String, ()> {
+    fn eat(&mut self) -> Result<String, Diagnostic> {
         let lexeme = self.current().lexeme;
         self.next()?;
 
         Ok(lexeme)
     }
 
-    fn eat_lexeme(&mut self, lexeme: &str) -> Result<String, ()> {
+    fn eat_lexeme(&mut self, lexeme: &str) -> Result<String, Diagnostic> {
         if self.current_lexeme() == lexeme {
             let lexeme = self.current().lexeme;
             self.next()?;
 
             Ok(lexeme)
         } else {
-            Err(response!(
-                Wrong(format!(
+            Err(Diagnostic::wrong(
+                format!(
                     "expected `{}`, found `{}`",
                     lexeme,
                     self.current_lexeme()
-                )),
-                self.source.file,
+                ),
+                &self.source.file,
                 self.current_position()
             ))
         }
     }
 
-    fn eat_type(&mut self, token_type: &TokenType) -> Result<String, ()> {
+    fn eat_type(&mut self, token_type: &TokenType) -> Result<String, Diagnostic> {
         if self.current_type() == *token_type {
             let lexeme = self.current().lexeme.clone();
             self.next()?;
 
             Ok(lexeme)
         } else {
-            Err(response!(
-                Wrong(format!(
+            Err(Diagnostic::wrong(
+                format!(
                     "expected `{}`, found `{}`",
                     token_type,
                     self.current_type()
-                )),
-                self.source.file,
+                ),
+                &self.source.file,
                 self.current_position()
             ))
         }
@@ -829,57 +1165,41 @@ impl<'p> Parser<'p> {
         self.current().token_type
     }
 
-    fn expect_type(&self, token_type: TokenType) -> Result<(), ()> {
+    fn expect_type(&self, token_type: TokenType) -> Result<(), Diagnostic> {
         if self.current_type() == token_type {
             Ok(())
         } else {
-            Err(response!(
-                Wrong(format!(
+            Err(Diagnostic::wrong(
+                format!(
                     "expected `{}`, found `{}`",
                     token_type,
                     self.current_type()
-                )),
-                self.source.file,
-                self.current_position()
-            ))
-        }
-    }
-
-    fn expect_lexeme(&self, lexeme: &str) -> Result<(), ()> {
-        if self.current_lexeme() == lexeme {
-            Ok(())
-        } else {
-            Err(response!(
-                Wrong(format!(
-                    "expected `{}`, found `{}`",
-                    lexeme,
-                    self.current_lexeme()
-                )),
-                self.source.file,
+                ),
+                &self.source.file,
                 self.current_position()
             ))
         }
     }
 
-    pub fn fold_expression(expression: &Expression) -> Result<Expression, ()> {
+    pub fn fold_expression(expression: &Expression) -> Result<Expression, Diagnostic> {
         use self::ExpressionNode::*;
         use self::Operator::*;
 
         let node = match expression.node {
             Binary(ref left, ref op, ref right) => {
                 let node = match (
-                    &Self::fold_expression(&*left)?.node,
+                    &Self::fold_expression(left)?.node,
                     op,
-                    &Self::fold_expression(&*right)?.node,
+                    &Self::fold_expression(right)?.node,
                 ) {
-                    (&Int(ref a), &Add, &Int(ref b)) => Int(a + b),
-                    (&Float(ref a), &Add, &Float(ref b)) => Float(a + b),
-                    (&Int(ref a), &Sub, &Int(ref b)) => Int(a - b),
-                    (&Float(ref a), &Sub, &Float(ref b)) => Float(a - b),
-                    (&Int(ref a), &Mul, &Int(ref b)) => Int(a * b),
-                    (&Float(ref a), &Mul, &Float(ref b)) => Float(a * b),
-                    (&Int(ref a), &Div, &Int(ref b)) => Int(a / b),
-                    (&Float(ref a), &Div, &Float(ref b)) => Float(a / b),
+                    (Int(a), Add, Int(b)) => Int(a + b),
+                    (Float(a), Add, Float(b)) => Float(a + b),
+                    (Int(a), Sub, Int(b)) => Int(a - b),
+                    (Float(a), Sub, Float(b)) => Float(a - b),
+                    (Int(a), Mul, Int(b)) => Int(a * b),
+                    (Float(a), Mul, Float(b)) => Float(a * b),
+                    (Int(a), Div, Int(b)) => Int(a / b),
+                    (Float(a), Div, Float(b)) => Float(a / b),
 
                     _ => expression.node.clone(),
                 };
@@ -893,3 +1213,47 @@ impl<'p> Parser<'p> {
         Ok(node)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::niels::engine::Engine;
+    use crate::niels::error::{Response, DIAGNOSTIC_LIMIT};
+    use crate::niels::parser::parse;
+    use crate::niels::source::Source;
+
+    fn diagnostics(code: &str) -> Vec<Response<String>> {
+        let source = Source::from("<test>", code.lines().map(String::from).collect());
+
+        parse(&source).err().unwrap_or_default().into_iter().map(|diagnostic| diagnostic.response).collect()
+    }
+
+    #[test]
+    fn lexer_and_parser_errors_are_reported_together() {
+        let responses = diagnostics("x = 1 $ 2\ny = )");
+
+        assert_eq!(responses.first(), Some(&Response::Wrong(String::from("bumped into weird character"))));
+        assert_eq!(responses.last(), Some(&Response::Wrong(String::from("unexpected symbol `)`"))));
+    }
+
+    #[test]
+    fn lexer_errors_stop_at_the_limit_with_a_note() {
+        let responses = diagnostics(&vec!("x = $"; DIAGNOSTIC_LIMIT + 5).join("\n"));
+
+        assert_eq!(responses.len(), DIAGNOSTIC_LIMIT + 1);
+        assert_eq!(responses.last(), Some(&Response::Note(format!("stopped after {} errors", DIAGNOSTIC_LIMIT))));
+    }
+
+    #[test]
+    fn int_literals_too_large_for_i64_are_diagnosed() {
+        assert!(Engine::new().eval::<()>("print(99999999999999999999999)").is_err());
+    }
+
+    #[test]
+    fn int_literals_count_their_sign() {
+        assert_eq!(Engine::new().eval::<i32>("-2147483648").unwrap(), i32::MIN);
+        assert_eq!(Engine::new().eval::<i32>("2147483647").unwrap(), i32::MAX);
+
+        assert!(Engine::new().eval::<i32>("-2147483649").is_err());
+        assert!(Engine::new().eval::<i32>("2147483648").is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 
 use std::fmt;
 
 use colored::Colorize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FilePath(pub String);
 
 impl fmt::Display for FilePath {
@@ -21,16 +22,16 @@ pub struct Source {
 }
 
 impl Source {
-    pub fn new(path: String) -> Self {
-        let mut source = File::open(path.as_str()).unwrap();
+    pub fn new(path: String) -> io::Result<Self> {
+        let mut source = File::open(path.as_str())?;
         let mut content = String::new();
 
-        source.read_to_string(&mut content).unwrap();
+        source.read_to_string(&mut content)?;
 
-        Source {
+        Ok(Source {
             file: FilePath(path),
             lines: content.lines().map(|x| x.to_string()).collect(),
-        }
+        })
     }
 
     pub fn from(path: &str, lines: Vec<String>) -> Self {